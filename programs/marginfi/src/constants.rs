@@ -4,6 +4,8 @@ use fixed_macro::types::I80F48;
 
 pub const MAX_ORACLE_KEYS: usize = 5;
 pub const ASSET_TAG_DEFAULT: u8 = 0;
+pub const ASSET_TAG_SOL: u8 = 1;
+pub const ASSET_TAG_STAKED: u8 = 2;
 pub const TOTAL_ASSET_VALUE_INIT_LIMIT_INACTIVE: u64 = 0;
 
 pub const EMISSIONS_AUTH_SEED: &str = "emissions_auth_seed";
@@ -28,6 +30,11 @@ pub const PROTOCOL_FEE_FIXED_DEFAULT: I80F48 = I80F48!(0.01);
 pub const PERMISSIONLESS_BAD_DEBT_SETTLEMENT_FLAG: u64 = 1 << 2;
 
 pub const PYTH_PUSH_MIGRATED: u8 = 1 << 0;
+/// If set, `BankConfig::validate` rejects an active `borrow_limit` above an active `deposit_limit`
+pub const BORROW_LIMIT_WITHIN_DEPOSIT_LIMIT: u8 = 1 << 1;
+/// If set, the bank's own program fee override is charged instead of the group's
+/// `fee_state_cache` program fees
+pub const PROGRAM_FEE_OVERRIDE: u8 = 1 << 2;
 
 // Some of the Bank's configurations are frozen and cannot be changed.
 pub const FREEZE_SETTINGS: u64 = 1 << 3;
@@ -41,7 +48,19 @@ pub const EMISSION_FLAG_LENDING_ACTIVE: u64 = 1 << 1;
 pub(crate) const EMISSION_FLAGS: u64 = EMISSION_FLAG_BORROW_ACTIVE | EMISSION_FLAG_LENDING_ACTIVE;
 pub(crate) const GROUP_FLAGS: u64 = PERMISSIONLESS_BAD_DEBT_SETTLEMENT_FLAG | FREEZE_SETTINGS;
 
+/// Bits returned by `MarginfiGroup::caller_capabilities`
+pub const CAPABILITY_ADMIN: u64 = 1 << 0;
+pub const CAPABILITY_EMODE_ADMIN: u64 = 1 << 1;
+
 pub const SECONDS_PER_YEAR: I80F48 = I80F48!(31_536_000);
+/// Interest accrual never applies more than this many seconds at once (one year), bounding the
+/// worst-case compounding on a bank that has not been touched in a very long time.
+pub const MAX_ACCRUAL_INTERVAL: u64 = 31_536_000;
+
+/// Banks cannot charge more than 10% of the amount deposited as a deposit fee
+pub const MAX_DEPOSIT_FEE: I80F48 = I80F48!(0.1);
+/// Banks cannot charge more than 10% of the amount withdrawn as a withdrawal fee
+pub const MAX_WITHDRAWAL_FEE: I80F48 = I80F48!(0.1);
 
 /// Due to real-world constraints, oracles using an age less than this value are typically too
 /// unreliable, and we want to restrict pools from picking an oracle that is effectively unusable
@@ -58,4 +58,14 @@ pub enum MarginfiError {
     InvalidFeesDestinationAccount,
     #[msg("Banks cannot close when they have open positions or emissions outstanding")] // 6081
     BankCannotClose,
+    #[msg("Not enough liquidity available in the bank")] // 6082
+    InsufficientLiquidity,
+    #[msg("Deposit amount is too small to mint any shares")] // 6083
+    DepositTooSmall,
+    #[msg("Bank has bad debt (liabilities exceed assets) that must be settled first")] // 6084
+    BankHasBadDebt,
+    #[msg("The timelock delay has not elapsed yet")] // 6085
+    TimelockNotElapsed,
+    #[msg("Bank share totals are negative")] // 6086
+    NegativeShareTotal,
 }
@@ -89,9 +89,7 @@ pub fn lending_pool_collect_bank_fees<'info>(
     bank.collected_group_fees_outstanding = new_outstanding_group_fees.into();
 
     bank.withdraw_spl_transfer(
-        group_fee_transfer_amount
-            .checked_to_num()
-            .ok_or_else(math_error!())?,
+        utils::i80f48_to_u64_checked(group_fee_transfer_amount)?,
         liquidity_vault.to_account_info(),
         fee_vault.to_account_info(),
         liquidity_vault_authority.to_account_info(),
@@ -106,9 +104,7 @@ pub fn lending_pool_collect_bank_fees<'info>(
     )?;
 
     bank.withdraw_spl_transfer(
-        insurance_fee_transfer_amount
-            .checked_to_num()
-            .ok_or_else(math_error!())?,
+        utils::i80f48_to_u64_checked(insurance_fee_transfer_amount)?,
         liquidity_vault.to_account_info(),
         insurance_vault.to_account_info(),
         liquidity_vault_authority.to_account_info(),
@@ -144,9 +140,7 @@ pub fn lending_pool_collect_bank_fees<'info>(
     bank.collected_program_fees_outstanding = new_outstanding_program_fees.into();
 
     bank.withdraw_spl_transfer(
-        program_fee_transfer_amount
-            .checked_to_num()
-            .ok_or_else(math_error!())?,
+        utils::i80f48_to_u64_checked(program_fee_transfer_amount)?,
         liquidity_vault.to_account_info(),
         fee_ata.to_account_info(),
         liquidity_vault_authority.to_account_info(),
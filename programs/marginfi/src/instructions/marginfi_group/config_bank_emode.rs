@@ -17,7 +17,7 @@ pub fn lending_pool_configure_bank_emode(
     sorted_entries.sort_by_key(|e| e.collateral_bank_emode_tag);
 
     for entry in sorted_entries.iter_mut() {
-        entry.pad0 = [0; 5];
+        entry.pad0 = [0; 1];
     }
 
     bank.emode.emode_tag = emode_tag;
@@ -5,6 +5,7 @@ use crate::{assert_struct_align, assert_struct_size, check};
 use anchor_lang::prelude::*;
 use bytemuck::{Pod, Zeroable};
 use fixed::types::I80F48;
+use std::collections::BTreeMap;
 use type_layout::TypeLayout;
 
 // Enable eMode flag
@@ -14,6 +15,11 @@ pub const EMODE_ON: u64 = 1;
 pub const MAX_EMODE_ENTRIES: usize = 10;
 // Represents an invalid tag, used as a sentinel value
 pub const EMODE_TAG_EMPTY: u16 = 0;
+// Entry flag: the entry applies to collateral of any tag, not just `collateral_bank_emode_tag`.
+// Reserved for catch-all support, currently only honored by `entries_for_collateral`.
+pub const EMODE_ENTRY_FLAG_CATCH_ALL: u8 = 1;
+// Entry liability weights are stored in basis points
+const BPS_PER_ONE: I80F48 = I80F48::lit("10000");
 
 assert_struct_size!(EmodeSettings, 424);
 assert_struct_align!(EmodeSettings, 8);
@@ -40,6 +46,16 @@ impl Default for EmodeSettings {
 
 impl EmodeSettings {
     pub fn validate_entries(&self) -> MarginfiResult {
+        self.validate_entries_with_liability_weight(None)
+    }
+
+    /// `validate_entries`, plus if `base_liability_weight_maint` (this bank's
+    /// `liability_weight_maint`) is given, rejects entries whose maint asset weight exceeds
+    /// `EmodeEntry::max_safe_asset_weight`.
+    pub fn validate_entries_with_liability_weight(
+        &self,
+        base_liability_weight_maint: Option<I80F48>,
+    ) -> MarginfiResult {
         for entry in self.emode_config.entries {
             if entry.is_empty() {
                 continue;
@@ -59,6 +75,30 @@ impl EmodeSettings {
             );
             // The maintenance mortgage rate must be ≥ the initial mortgage rate (otherwise the user will be liquidated as soon as the loan is completed)
             check!(asset_maint_w >= asset_init_w, MarginfiError::BadEmodeConfig);
+
+            if entry.has_liability_weights() {
+                let liab_init_w: I80F48 = entry.liability_weight_init();
+                let liab_maint_w: I80F48 = entry.liability_weight_maint();
+
+                // Liabilities are never discounted below face value, and the initial weight is
+                // the stricter of the two
+                check!(
+                    liab_init_w >= liab_maint_w && liab_maint_w >= I80F48::ONE,
+                    MarginfiError::BadEmodeConfig
+                );
+            }
+
+            if let Some(base_liability_weight_maint) = base_liability_weight_maint {
+                let max_safe_w = entry.max_safe_asset_weight(base_liability_weight_maint);
+                check!(
+                    asset_maint_w <= max_safe_w,
+                    MarginfiError::BadEmodeConfig,
+                    "emode tag {} maint asset weight {} exceeds safe bound {}",
+                    entry.collateral_bank_emode_tag,
+                    asset_maint_w,
+                    max_safe_w
+                );
+            }
         }
 
         // Check if there are duplicate tags in all entries
@@ -67,6 +107,26 @@ impl EmodeSettings {
         Ok(())
     }
 
+    /// Stricter than `validate_entries`: also rejects contradictory settings, i.e. emode enabled
+    /// without a tag for this bank, or entries present while emode is disabled.
+    pub fn validate_settings(&self) -> MarginfiResult {
+        if self.is_enabled() {
+            check!(
+                self.emode_tag != EMODE_TAG_EMPTY,
+                MarginfiError::BadEmodeConfig,
+                "emode is enabled but the bank has no emode tag"
+            );
+        } else {
+            check!(
+                !self.emode_config.has_entries(),
+                MarginfiError::BadEmodeConfig,
+                "emode is disabled but has entries"
+            );
+        }
+
+        self.validate_entries()
+    }
+
     fn check_dupes(&self) -> MarginfiResult {
         let non_empty_tags: Vec<u16> = self
             .emode_config
@@ -97,6 +157,56 @@ impl EmodeSettings {
         }
     }
 
+    /// The non-empty entries of `emode_config`, e.g. for tooling. Round-trips with
+    /// `EmodeConfig::from_entries`.
+    pub fn to_entries(&self) -> Vec<EmodeEntry> {
+        self.emode_config
+            .entries
+            .iter()
+            .filter(|e| !e.is_empty())
+            .copied()
+            .collect()
+    }
+
+    /// Largest asset weight increase (init or maint) of any tag present in both `previous` and
+    /// `self`. Zero if weights only stayed the same or decreased. Governance tooling can use this to
+    /// flag changes that meaningfully raise liquidation risk.
+    pub fn risk_delta(&self, previous: &EmodeSettings) -> I80F48 {
+        self.emode_config
+            .entries
+            .iter()
+            .filter(|e| !e.is_empty())
+            .filter_map(|e| {
+                let prev = previous
+                    .emode_config
+                    .find_with_tag(e.collateral_bank_emode_tag)?;
+                let init_delta =
+                    I80F48::from(e.asset_weight_init).saturating_sub(prev.asset_weight_init.into());
+                let maint_delta = I80F48::from(e.asset_weight_maint)
+                    .saturating_sub(prev.asset_weight_maint.into());
+                Some(init_delta.max(maint_delta))
+            })
+            .fold(I80F48::ZERO, I80F48::max)
+    }
+
+    /// True if any tag present in both `previous` and `self` had its maint asset weight lowered,
+    /// i.e. existing emode borrowers could become liquidatable and may deserve a grace period.
+    /// Note: tags removed entirely are not compared.
+    pub fn tightens_risk(&self, previous: &EmodeSettings) -> bool {
+        self.emode_config
+            .entries
+            .iter()
+            .filter(|e| !e.is_empty())
+            .any(|e| {
+                previous
+                    .emode_config
+                    .find_with_tag(e.collateral_bank_emode_tag)
+                    .is_some_and(|prev| {
+                        I80F48::from(e.asset_weight_maint) < I80F48::from(prev.asset_weight_maint)
+                    })
+            })
+    }
+
     /// Sets EMODE on flag if configuration has any entries, removes the flag if it has no entries.
     pub fn update_emode_enabled(&mut self) {
         if self.emode_config.has_entries() {
@@ -107,6 +217,18 @@ impl EmodeSettings {
     }
 }
 
+/// Total value per emode tag from `(tag, value)` position pairs, sorted by tag. Useful to spot
+/// concentration in one correlated-asset group.
+pub fn emode_exposure(positions: &[(u16, I80F48)]) -> Vec<(u16, I80F48)> {
+    let mut totals: BTreeMap<u16, I80F48> = BTreeMap::new();
+    for (tag, value) in positions {
+        let total = totals.entry(*tag).or_insert(I80F48::ZERO);
+        *total = total.saturating_add(*value);
+    }
+
+    totals.into_iter().collect()
+}
+
 assert_struct_size!(EmodeConfig, 400);
 assert_struct_align!(EmodeConfig, 8);
 #[repr(C)]
@@ -146,6 +268,35 @@ impl EmodeConfig {
     pub fn has_entries(&self) -> bool {
         self.entries.iter().any(|e| !e.is_empty())
     }
+
+    /// All non-empty entries that apply to collateral with `collateral_tag`: a direct tag match or
+    /// a catch-all entry (`EMODE_ENTRY_FLAG_CATCH_ALL`). Unlike `find_with_tag`, multiple entries
+    /// may be returned.
+    pub fn entries_for_collateral(&self, collateral_tag: u16) -> Vec<&EmodeEntry> {
+        if collateral_tag == EMODE_TAG_EMPTY {
+            return Vec::new();
+        }
+        self.entries
+            .iter()
+            .filter(|e| !e.is_empty())
+            .filter(|e| e.tag_equals(collateral_tag) || e.is_catch_all())
+            .collect()
+    }
+
+    /// Errors if any entry references a tag not in `known_tags`, e.g. the emode tags of every bank
+    /// in the group. Such entries can never apply and are dead config. Intended for admin tooling.
+    pub fn validate_against_tags(&self, known_tags: &[u16]) -> MarginfiResult {
+        for entry in self.entries.iter().filter(|e| !e.is_empty()) {
+            check!(
+                known_tags.contains(&entry.collateral_bank_emode_tag),
+                MarginfiError::BadEmodeConfig,
+                "emode entry references unknown tag {}",
+                entry.collateral_bank_emode_tag
+            );
+        }
+
+        Ok(())
+    }
 }
 
 assert_struct_size!(EmodeEntry, 40);
@@ -158,7 +309,15 @@ pub struct EmodeEntry {
     // Which type of collateral object is applicable to this strategy (e.g. tag=1 is a stablecoin)
     pub collateral_bank_emode_tag: u16,
     pub flags: u8,
-    pub pad0: [u8; 5],
+    pub pad0: [u8; 1],
+    // Initial liability weight when borrowing against this collateral, in basis points (10_000 =
+    // 1). Zero (along with `liability_weight_maint_bps`) means the bank's own liability weights
+    // apply. Stored in what used to be padding, so existing entries (zeroed) keep the bank's
+    // weights and the entry size is unchanged.
+    pub liability_weight_init_bps: u16,
+    // Liquidation liability weight when borrowing against this collateral, in basis points. Zero
+    // (along with `liability_weight_init_bps`) means the bank's own liability weights apply.
+    pub liability_weight_maint_bps: u16,
     // Initial asset weight for lending (affects the maximum loan amount)
     pub asset_weight_init: WrappedI80F48,
     // Liquidation asset weight (affects when liquidation occurs)
@@ -166,10 +325,308 @@ pub struct EmodeEntry {
 }
 
 impl EmodeEntry {
+    /// True if the entry overrides liability weights, i.e. either liability weight is non-zero
+    pub fn has_liability_weights(&self) -> bool {
+        self.liability_weight_init_bps != 0 || self.liability_weight_maint_bps != 0
+    }
+
+    /// `liability_weight_init_bps` as a weight, e.g. 10_500 bps is 1.05
+    pub fn liability_weight_init(&self) -> I80F48 {
+        I80F48::from_num(self.liability_weight_init_bps) / BPS_PER_ONE
+    }
+
+    /// `liability_weight_maint_bps` as a weight, e.g. 10_500 bps is 1.05
+    pub fn liability_weight_maint(&self) -> I80F48 {
+        I80F48::from_num(self.liability_weight_maint_bps) / BPS_PER_ONE
+    }
+
+    /// Highest maint asset weight this entry can safely use against a liability weighted at
+    /// `base_liability_weight` (the bank's `liability_weight_maint`, or the entry's own if set and
+    /// lower), so collateral never counts for more than the debt it backs. Init weights are capped at
+    /// 1 by `validate_entries` already, so only maint weights can exceed this.
+    pub fn max_safe_asset_weight(&self, base_liability_weight: I80F48) -> I80F48 {
+        if self.has_liability_weights() {
+            base_liability_weight.min(self.liability_weight_maint())
+        } else {
+            base_liability_weight
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.collateral_bank_emode_tag == EMODE_TAG_EMPTY
     }
     pub fn tag_equals(&self, tag: u16) -> bool {
         self.collateral_bank_emode_tag == tag
     }
+    pub fn is_catch_all(&self) -> bool {
+        self.flags & EMODE_ENTRY_FLAG_CATCH_ALL != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_with_tolerance;
+    use fixed_macro::types::I80F48;
+
+    fn entry(tag: u16, asset_weight_init: I80F48, asset_weight_maint: I80F48) -> EmodeEntry {
+        EmodeEntry {
+            collateral_bank_emode_tag: tag,
+            flags: 0,
+            pad0: [0; 1],
+            liability_weight_init_bps: 0,
+            liability_weight_maint_bps: 0,
+            asset_weight_init: asset_weight_init.into(),
+            asset_weight_maint: asset_weight_maint.into(),
+        }
+    }
+
+    #[test]
+    fn validate_against_tags_rejects_dangling_tag() {
+        let config = EmodeConfig::from_entries(&[
+            entry(1, I80F48!(0.9), I80F48!(0.95)),
+            entry(7, I80F48!(0.8), I80F48!(0.85)),
+        ]);
+
+        assert!(config.validate_against_tags(&[1, 2, 7]).is_ok());
+        assert!(config.validate_against_tags(&[1, 2]).is_err());
+        // Empty entries never need a matching tag
+        assert!(EmodeConfig::zeroed().validate_against_tags(&[]).is_ok());
+    }
+
+    #[test]
+    fn to_entries_round_trips_partial_config() {
+        let entries = [
+            entry(3, I80F48!(0.7), I80F48!(0.75)),
+            entry(1, I80F48!(0.9), I80F48!(0.95)),
+        ];
+        let settings = EmodeSettings {
+            emode_config: EmodeConfig::from_entries(&entries),
+            ..Default::default()
+        };
+
+        let exported = settings.to_entries();
+        // Sorted by tag, empty slots dropped
+        assert_eq!(exported, vec![entries[1], entries[0]]);
+        assert_eq!(EmodeConfig::from_entries(&exported), settings.emode_config);
+
+        assert!(EmodeSettings::default().to_entries().is_empty());
+    }
+
+    #[test]
+    fn risk_delta_reports_largest_weight_increase() {
+        let previous = EmodeSettings {
+            emode_config: EmodeConfig::from_entries(&[
+                entry(1, I80F48!(0.7), I80F48!(0.8)),
+                entry(2, I80F48!(0.5), I80F48!(0.6)),
+            ]),
+            ..Default::default()
+        };
+        let updated = EmodeSettings {
+            emode_config: EmodeConfig::from_entries(&[
+                entry(1, I80F48!(0.9), I80F48!(0.85)),
+                entry(2, I80F48!(0.55), I80F48!(0.6)),
+                // Tags absent from `previous` are not compared
+                entry(3, I80F48!(1), I80F48!(1)),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(updated.risk_delta(&previous), I80F48!(0.2));
+        // The reverse change only lowers weights
+        assert_eq!(previous.risk_delta(&updated), I80F48::ZERO);
+        assert_eq!(previous.risk_delta(&previous), I80F48::ZERO);
+    }
+
+    #[test]
+    fn tightens_risk_on_lower_maint_weight() {
+        let previous = EmodeSettings {
+            emode_config: EmodeConfig::from_entries(&[
+                entry(1, I80F48!(0.7), I80F48!(0.8)),
+                entry(2, I80F48!(0.5), I80F48!(0.6)),
+            ]),
+            ..Default::default()
+        };
+
+        // Tag 2 maint weight drops
+        let tightened = EmodeSettings {
+            emode_config: EmodeConfig::from_entries(&[
+                entry(1, I80F48!(0.7), I80F48!(0.8)),
+                entry(2, I80F48!(0.5), I80F48!(0.55)),
+            ]),
+            ..Default::default()
+        };
+        assert!(tightened.tightens_risk(&previous));
+
+        // Weights only go up, a lower init weight alone doesn't affect existing loans
+        let loosened = EmodeSettings {
+            emode_config: EmodeConfig::from_entries(&[
+                entry(1, I80F48!(0.65), I80F48!(0.85)),
+                entry(2, I80F48!(0.5), I80F48!(0.6)),
+                entry(3, I80F48!(0.1), I80F48!(0.2)),
+            ]),
+            ..Default::default()
+        };
+        assert!(!loosened.tightens_risk(&previous));
+        assert!(!previous.tightens_risk(&previous));
+    }
+
+    #[test]
+    fn validate_settings_rejects_contradictions() {
+        let mut settings = EmodeSettings {
+            emode_tag: 1,
+            emode_config: EmodeConfig::from_entries(&[entry(2, I80F48!(0.8), I80F48!(0.9))]),
+            ..Default::default()
+        };
+        settings.update_emode_enabled();
+        assert!(settings.validate_settings().is_ok());
+
+        // Enabled with an empty tag
+        settings.emode_tag = EMODE_TAG_EMPTY;
+        assert_eq!(
+            settings.validate_settings(),
+            Err(MarginfiError::BadEmodeConfig.into())
+        );
+        assert!(settings.validate_entries().is_ok());
+
+        // Entries present while disabled
+        settings.emode_tag = 1;
+        settings.set_emode_enabled(false);
+        assert_eq!(
+            settings.validate_settings(),
+            Err(MarginfiError::BadEmodeConfig.into())
+        );
+
+        // Disabled with no entries is fine
+        settings.emode_config = EmodeConfig::from_entries(&[]);
+        assert!(settings.validate_settings().is_ok());
+    }
+
+    #[test]
+    fn max_safe_asset_weight_bounds_maint_weight() {
+        let plain = entry(1, I80F48!(0.9), I80F48!(1.15));
+        assert_eq!(plain.max_safe_asset_weight(I80F48!(1.1)), I80F48!(1.1));
+
+        // The entry's own, lower liability weight tightens the bound
+        let with_liab_weights = EmodeEntry {
+            liability_weight_init_bps: 11_000,
+            liability_weight_maint_bps: 10_500,
+            ..entry(2, I80F48!(0.9), I80F48!(1.08))
+        };
+        assert_eq_with_tolerance!(
+            with_liab_weights.max_safe_asset_weight(I80F48!(1.1)),
+            I80F48!(1.05),
+            I80F48!(0.000001)
+        );
+
+        let settings = EmodeSettings {
+            emode_config: EmodeConfig::from_entries(&[plain]),
+            ..Default::default()
+        };
+        // Only enforced when asked to
+        assert!(settings.validate_entries().is_ok());
+        assert!(settings
+            .validate_entries_with_liability_weight(Some(I80F48!(1.2)))
+            .is_ok());
+        // 1.15 > 1.1
+        assert_eq!(
+            settings.validate_entries_with_liability_weight(Some(I80F48!(1.1))),
+            Err(MarginfiError::BadEmodeConfig.into())
+        );
+
+        let settings = EmodeSettings {
+            emode_config: EmodeConfig::from_entries(&[with_liab_weights]),
+            ..Default::default()
+        };
+        // 1.08 > 1.05
+        assert!(settings
+            .validate_entries_with_liability_weight(Some(I80F48!(1.1)))
+            .is_err());
+    }
+
+    #[test]
+    fn validate_entries_liability_weight_invariants() {
+        let with_liab_weights =
+            |liability_weight_init_bps: u16, liability_weight_maint_bps: u16| EmodeSettings {
+                emode_config: EmodeConfig::from_entries(&[EmodeEntry {
+                    liability_weight_init_bps,
+                    liability_weight_maint_bps,
+                    ..entry(1, I80F48!(0.8), I80F48!(0.9))
+                }]),
+                ..Default::default()
+            };
+
+        // Unset liability weights inherit the bank's
+        assert!(with_liab_weights(0, 0).validate_entries().is_ok());
+        assert!(with_liab_weights(11_000, 10_500).validate_entries().is_ok());
+        assert!(with_liab_weights(10_000, 10_000).validate_entries().is_ok());
+
+        // Init weight must be at least the maint weight
+        assert!(with_liab_weights(10_500, 11_000)
+            .validate_entries()
+            .is_err());
+        // Liabilities are never weighted below 1
+        assert!(with_liab_weights(11_000, 9_500).validate_entries().is_err());
+        // Setting only one of the pair is invalid
+        assert!(with_liab_weights(11_000, 0).validate_entries().is_err());
+    }
+
+    #[test]
+    fn entries_for_collateral_direct_and_catch_all() {
+        let direct = entry(2, I80F48!(0.9), I80F48!(0.95));
+        let catch_all = EmodeEntry {
+            flags: EMODE_ENTRY_FLAG_CATCH_ALL,
+            ..entry(5, I80F48!(0.6), I80F48!(0.7))
+        };
+        let other = entry(3, I80F48!(0.8), I80F48!(0.85));
+        let config = EmodeConfig::from_entries(&[direct, catch_all, other]);
+
+        assert_eq!(config.entries_for_collateral(2), vec![&direct, &catch_all]);
+        // Only the catch-all applies to an otherwise unknown tag
+        assert_eq!(config.entries_for_collateral(9), vec![&catch_all]);
+        assert!(config.entries_for_collateral(EMODE_TAG_EMPTY).is_empty());
+    }
+
+    #[test]
+    fn emode_exposure_aggregates_by_tag() {
+        let positions = [
+            (2, I80F48!(100)),
+            (1, I80F48!(50)),
+            (2, I80F48!(25.5)),
+            (7, I80F48!(10)),
+            (1, I80F48!(50)),
+        ];
+
+        assert_eq!(
+            emode_exposure(&positions),
+            vec![(1, I80F48!(100)), (2, I80F48!(125.5)), (7, I80F48!(10))]
+        );
+        assert!(emode_exposure(&[]).is_empty());
+    }
+
+    #[test]
+    fn legacy_entry_bytes_inherit_bank_liability_weights() {
+        // An entry written before liability weights existed: tag, flags, zeroed padding, then the
+        // two asset weights
+        let legacy = entry(1, I80F48!(0.8), I80F48!(0.9));
+        let mut bytes = [0u8; 40];
+        bytes[0..2].copy_from_slice(&1u16.to_le_bytes());
+        bytes[8..24].copy_from_slice(&legacy.asset_weight_init.value);
+        bytes[24..40].copy_from_slice(&legacy.asset_weight_maint.value);
+
+        let loaded: EmodeEntry = bytemuck::pod_read_unaligned(&bytes);
+        assert_eq!(loaded, legacy);
+        assert!(!loaded.has_liability_weights());
+
+        // The weights live in the former padding, ahead of the asset weights
+        let with_liab_weights = EmodeEntry {
+            liability_weight_init_bps: 11_000,
+            liability_weight_maint_bps: 10_500,
+            ..legacy
+        };
+        let bytes = bytemuck::bytes_of(&with_liab_weights);
+        assert_eq!(&bytes[4..6], &11_000u16.to_le_bytes());
+        assert_eq!(&bytes[6..8], &10_500u16.to_le_bytes());
+        assert_eq!(&bytes[8..40], &bytemuck::bytes_of(&legacy)[8..40]);
+    }
 }
@@ -1,11 +1,12 @@
 use super::price::OraclePriceFeedAdapter;
 use crate::constants::EXP_10_I80F48;
-use crate::math_error;
+use crate::errors::MarginfiError;
 use crate::prelude::MarginfiResult;
 use crate::state::health_cache::HealthCache;
 use crate::state::marginfi_group::{Bank, WrappedI80F48};
 use crate::state::price::OraclePriceType;
 use crate::{assert_struct_align, assert_struct_size};
+use crate::{check, math_error};
 use anchor_lang::prelude::*;
 use bytemuck::{Pod, Zeroable};
 use fixed::types::I80F48;
@@ -105,6 +106,201 @@ pub fn calc_value(
     Ok(value)
 }
 
+/// `10^decimals`, the number of native units in one token of a mint with `decimals`. Errors with
+/// `MathError` past the largest supported exponent.
+pub fn decimal_scale_factor(decimals: u8) -> MarginfiResult<I80F48> {
+    Ok(*EXP_10_I80F48
+        .get(decimals as usize)
+        .ok_or_else(math_error!())?)
+}
+
+/// Convert `amount` (native units of a mint with `from_decimals`, priced at `from_price`) into the
+/// equivalent native amount of a mint with `to_decimals` priced at `to_price`, e.g. the liability
+/// tokens a liquidator must repay for some collateral. Prices are per whole token, in the same
+/// quote currency. Errors with `MathError` if `to_price` is not positive.
+pub fn convert_value(
+    amount: I80F48,
+    from_price: I80F48,
+    from_decimals: u8,
+    to_price: I80F48,
+    to_decimals: u8,
+) -> MarginfiResult<I80F48> {
+    check!(to_price > I80F48::ZERO, MarginfiError::MathError);
+
+    let value = calc_value(amount, from_price, from_decimals, None)?;
+
+    Ok(value
+        .checked_mul(decimal_scale_factor(to_decimals)?)
+        .ok_or_else(math_error!())?
+        .checked_div(to_price)
+        .ok_or_else(math_error!())?)
+}
+
+/// Minimum collateral value needed to back `liability_value` without falling below the
+/// maintenance requirement, i.e. `liability_value * liability_weight_maint / asset_weight_maint`.
+pub fn min_collateral_for_liability(
+    liability_value: I80F48,
+    liability_weight_maint: I80F48,
+    asset_weight_maint: I80F48,
+) -> MarginfiResult<I80F48> {
+    Ok(liability_value
+        .checked_mul(liability_weight_maint)
+        .ok_or_else(math_error!())?
+        .checked_div(asset_weight_maint)
+        .ok_or_else(math_error!())?)
+}
+
+/// Collateral value that becomes withdrawable (without dropping below the maintenance
+/// requirement) after repaying `repay_value` of a liability: exactly the collateral that no longer
+/// needs to back it, see `min_collateral_for_liability`.
+pub fn collateral_freed_by_repay(
+    repay_value: I80F48,
+    liability_weight_maint: I80F48,
+    asset_weight_maint: I80F48,
+) -> MarginfiResult<I80F48> {
+    min_collateral_for_liability(repay_value, liability_weight_maint, asset_weight_maint)
+}
+
+/// Liability value a liquidator must repay to seize `seize_value` of collateral, which they
+/// receive at a `liquidation_discount` (from 0 inclusive to 1 exclusive), i.e.
+/// `seize_value * (1 - liquidation_discount)`.
+pub fn repay_for_seizure(
+    seize_value: I80F48,
+    liquidation_discount: I80F48,
+) -> MarginfiResult<I80F48> {
+    check!(
+        liquidation_discount >= I80F48::ZERO && liquidation_discount < I80F48::ONE,
+        MarginfiError::InvalidConfig
+    );
+
+    Ok(seize_value
+        .checked_mul(I80F48::ONE - liquidation_discount)
+        .ok_or_else(math_error!())?)
+}
+
+/// Smallest `liquidation_discount` (see `repay_for_seizure`) at which seizing `seize_value` of
+/// collateral earns a liquidator at least `gas_cost_value`, i.e. `gas_cost_value / seize_value`.
+/// A result of 1 or more means no valid discount makes the liquidation profitable. Errors with
+/// `MathError` if `seize_value` is not positive or `gas_cost_value` is negative.
+pub fn min_profitable_incentive(
+    gas_cost_value: I80F48,
+    seize_value: I80F48,
+) -> MarginfiResult<I80F48> {
+    check!(
+        seize_value > I80F48::ZERO && gas_cost_value >= I80F48::ZERO,
+        MarginfiError::MathError
+    );
+
+    Ok(gas_cost_value
+        .checked_div(seize_value)
+        .ok_or_else(math_error!())?)
+}
+
+/// Collateral price at which `collateral_amount` (native units) of collateral, weighted by
+/// `asset_weight_maint`, exactly covers `liability_value` weighted by `liability_weight_maint`.
+/// Below this price, the position can be liquidated.
+pub fn liquidation_price(
+    collateral_amount: I80F48,
+    collateral_decimals: u8,
+    asset_weight_maint: I80F48,
+    liability_value: I80F48,
+    liability_weight_maint: I80F48,
+) -> MarginfiResult<I80F48> {
+    // Value of the weighted collateral if it were priced at 1
+    let weighted_collateral_per_unit_price = calc_value(
+        collateral_amount,
+        I80F48::ONE,
+        collateral_decimals,
+        Some(asset_weight_maint),
+    )?;
+    let weighted_liabilities = liability_value
+        .checked_mul(liability_weight_maint)
+        .ok_or_else(math_error!())?;
+
+    Ok(weighted_liabilities
+        .checked_div(weighted_collateral_per_unit_price)
+        .ok_or_else(math_error!())?)
+}
+
+/// Numeric health factor, i.e. `weighted_assets / weighted_liabilities`. Below 1 the account is
+/// liquidatable. `I80F48::MAX` if there are no liabilities.
+pub fn health_factor(weighted_assets: I80F48, weighted_liabilities: I80F48) -> I80F48 {
+    if weighted_liabilities == I80F48::ZERO {
+        return I80F48::MAX;
+    }
+
+    weighted_assets.saturating_div(weighted_liabilities)
+}
+
+/// Health factor after a liquidation that repays `repay_value` of weighted liabilities and seizes
+/// `seize_weighted_value` of weighted collateral. Both sides are floored at zero.
+pub fn health_after_liquidation(
+    weighted_assets: I80F48,
+    weighted_liabilities: I80F48,
+    repay_value: I80F48,
+    seize_weighted_value: I80F48,
+) -> I80F48 {
+    let assets = weighted_assets
+        .saturating_sub(seize_weighted_value)
+        .max(I80F48::ZERO);
+    let liabilities = weighted_liabilities
+        .saturating_sub(repay_value)
+        .max(I80F48::ZERO);
+
+    health_factor(assets, liabilities)
+}
+
+/// Maximum loan-to-value for a collateral/liability pair, i.e. `asset_weight_init /
+/// liability_weight_init` (after any emode adjustment), clamped to `[0, 1]`. Zero if the liability
+/// weight is not positive.
+pub fn max_ltv(asset_weight_init: I80F48, liability_weight_init: I80F48) -> I80F48 {
+    if liability_weight_init <= I80F48::ZERO {
+        return I80F48::ZERO;
+    }
+
+    asset_weight_init
+        .saturating_div(liability_weight_init)
+        .clamp(I80F48::ZERO, I80F48::ONE)
+}
+
+/// Largest liability value a single liquidation may repay, i.e. `liability_value * close_factor`.
+/// `close_factor` must be in `(0, 1]`, 1 allows closing the whole position at once.
+pub fn max_liquidation_amount(
+    liability_value: I80F48,
+    close_factor: I80F48,
+) -> MarginfiResult<I80F48> {
+    check!(
+        close_factor > I80F48::ZERO && close_factor <= I80F48::ONE,
+        MarginfiError::InvalidConfig
+    );
+
+    Ok(liability_value
+        .checked_mul(close_factor)
+        .ok_or_else(math_error!())?)
+}
+
+/// Each position's share of the total, e.g. of an account's weighted exposure across banks, to flag
+/// over-concentration in one bank. Values must not be negative. All zero if the total is zero.
+pub fn concentration_weights(position_values: &[I80F48]) -> MarginfiResult<Vec<I80F48>> {
+    check!(
+        position_values.iter().all(|value| *value >= I80F48::ZERO),
+        MarginfiError::InvalidConfig
+    );
+
+    let total = position_values
+        .iter()
+        .try_fold(I80F48::ZERO, |total, value| total.checked_add(*value))
+        .ok_or_else(math_error!())?;
+    if total == I80F48::ZERO {
+        return Ok(vec![I80F48::ZERO; position_values.len()]);
+    }
+
+    position_values
+        .iter()
+        .map(|value| Ok(value.checked_div(total).ok_or_else(math_error!())?))
+        .collect()
+}
+
 #[derive(Copy, Clone)]
 pub enum RequirementType {
     Initial,
@@ -130,3 +326,265 @@ pub enum BalanceSide {
     Assets,
     Liabilities,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_with_tolerance;
+    use fixed_macro::types::I80F48;
+
+    #[test]
+    fn min_collateral_for_liability_representative_weights() {
+        // Stablecoin-like: maint weights 0.95 / 1.05
+        assert_eq_with_tolerance!(
+            min_collateral_for_liability(I80F48!(1000), I80F48!(1.05), I80F48!(0.95)).unwrap(),
+            I80F48!(1105.263157),
+            I80F48!(0.000001)
+        );
+        // Volatile: maint weights 0.5 / 1.25
+        assert_eq!(
+            min_collateral_for_liability(I80F48!(1000), I80F48!(1.25), I80F48!(0.5)).unwrap(),
+            I80F48!(2500)
+        );
+        // Unit weights require exactly the liability value
+        assert_eq!(
+            min_collateral_for_liability(I80F48!(1000), I80F48::ONE, I80F48::ONE).unwrap(),
+            I80F48!(1000)
+        );
+        // No liability, no collateral needed
+        assert_eq!(
+            min_collateral_for_liability(I80F48::ZERO, I80F48!(1.25), I80F48!(0.5)).unwrap(),
+            I80F48::ZERO
+        );
+    }
+
+    #[test]
+    fn min_collateral_for_liability_zero_asset_weight_errors() {
+        assert!(min_collateral_for_liability(I80F48!(1000), I80F48::ONE, I80F48::ZERO).is_err());
+    }
+
+    #[test]
+    fn repay_for_seizure_discount_levels() {
+        let seize_value = I80F48!(1_000);
+
+        assert_eq!(
+            repay_for_seizure(seize_value, I80F48::ZERO).unwrap(),
+            I80F48!(1_000)
+        );
+        assert_eq_with_tolerance!(
+            repay_for_seizure(seize_value, I80F48!(0.025)).unwrap(),
+            I80F48!(975),
+            I80F48!(0.000001)
+        );
+        assert_eq_with_tolerance!(
+            repay_for_seizure(seize_value, I80F48!(0.1)).unwrap(),
+            I80F48!(900),
+            I80F48!(0.000001)
+        );
+        assert_eq!(
+            repay_for_seizure(seize_value, I80F48!(0.5)).unwrap(),
+            I80F48!(500)
+        );
+
+        assert!(repay_for_seizure(seize_value, I80F48::ONE).is_err());
+        assert!(repay_for_seizure(seize_value, I80F48!(-0.01)).is_err());
+    }
+
+    #[test]
+    fn liquidation_price_worked_example() {
+        // 10 SOL (9 decimals) at 80% maint weight backing a $1,000 loan at 100% maint weight:
+        // 10 * 0.8 * price = 1,000 -> price = 125
+        let price = liquidation_price(
+            I80F48!(10_000_000_000),
+            9,
+            I80F48!(0.8),
+            I80F48!(1_000),
+            I80F48::ONE,
+        )
+        .unwrap();
+        assert_eq_with_tolerance!(price, I80F48!(125), I80F48!(0.000001));
+
+        // At that price the weighted collateral exactly equals the weighted liability
+        assert_eq_with_tolerance!(
+            calc_value(I80F48!(10_000_000_000), price, 9, Some(I80F48!(0.8))).unwrap(),
+            I80F48!(1_000),
+            I80F48!(0.000001)
+        );
+
+        // A 1.25 liability weight raises the liquidation price proportionally
+        assert_eq_with_tolerance!(
+            liquidation_price(
+                I80F48!(10_000_000_000),
+                9,
+                I80F48!(0.8),
+                I80F48!(1_000),
+                I80F48!(1.25),
+            )
+            .unwrap(),
+            I80F48!(156.25),
+            I80F48!(0.000001)
+        );
+
+        // No collateral, no price can save the position
+        assert!(
+            liquidation_price(I80F48::ZERO, 9, I80F48!(0.8), I80F48!(1_000), I80F48::ONE).is_err()
+        );
+    }
+
+    #[test]
+    fn health_factor_cases() {
+        // Healthy
+        assert_eq!(health_factor(I80F48!(150), I80F48!(100)), I80F48!(1.5));
+        // Exactly at the threshold
+        assert_eq!(health_factor(I80F48!(100), I80F48!(100)), I80F48::ONE);
+        // Liquidatable
+        let hf = health_factor(I80F48!(90), I80F48!(100));
+        assert_eq!(hf, I80F48!(0.9));
+        assert!(hf < I80F48::ONE);
+        // No liabilities
+        assert_eq!(health_factor(I80F48!(100), I80F48::ZERO), I80F48::MAX);
+    }
+
+    #[test]
+    fn health_after_liquidation_restores_health() {
+        // Liquidatable at 0.95
+        let (assets, liabilities) = (I80F48!(95), I80F48!(100));
+        assert!(health_factor(assets, liabilities) < I80F48::ONE);
+
+        // Repay 50 of liabilities, seize 44 of weighted collateral: 51 / 50 = 1.02
+        let hf = health_after_liquidation(assets, liabilities, I80F48!(50), I80F48!(44));
+        assert_eq!(hf, I80F48!(1.02));
+        assert!(hf > I80F48::ONE);
+
+        // Seizing too much relative to the repayment makes health worse
+        let hf = health_after_liquidation(assets, liabilities, I80F48!(50), I80F48!(50));
+        assert_eq!(hf, I80F48!(0.9));
+
+        // Repaying everything leaves no liabilities
+        assert_eq!(
+            health_after_liquidation(assets, liabilities, I80F48!(100), I80F48!(95)),
+            I80F48::MAX
+        );
+    }
+
+    #[test]
+    fn collateral_freed_by_repay_representative_weights() {
+        // Volatile collateral: 100 * 1.1 / 0.8
+        assert_eq_with_tolerance!(
+            collateral_freed_by_repay(I80F48!(100), I80F48!(1.1), I80F48!(0.8)).unwrap(),
+            I80F48!(137.5),
+            I80F48!(0.000001)
+        );
+        // Stablecoin pair: 100 * 1 / 0.95
+        assert_eq_with_tolerance!(
+            collateral_freed_by_repay(I80F48!(100), I80F48::ONE, I80F48!(0.95)).unwrap(),
+            I80F48!(105.263157),
+            I80F48!(0.000001)
+        );
+        assert_eq!(
+            collateral_freed_by_repay(I80F48::ZERO, I80F48!(1.1), I80F48!(0.8)).unwrap(),
+            I80F48::ZERO
+        );
+        // Collateral with no maint weight never backed the loan
+        assert!(collateral_freed_by_repay(I80F48!(100), I80F48!(1.1), I80F48::ZERO).is_err());
+    }
+
+    #[test]
+    fn concentration_weights_fractions_of_total() {
+        let weights = concentration_weights(&[I80F48!(500), I80F48!(300), I80F48!(200)]).unwrap();
+        assert_eq_with_tolerance!(weights[0], I80F48!(0.5), I80F48!(0.000001));
+        assert_eq_with_tolerance!(weights[1], I80F48!(0.3), I80F48!(0.000001));
+        assert_eq_with_tolerance!(weights[2], I80F48!(0.2), I80F48!(0.000001));
+        assert_eq_with_tolerance!(
+            weights.iter().fold(I80F48::ZERO, |sum, w| sum + *w),
+            I80F48::ONE,
+            I80F48!(0.000001)
+        );
+
+        assert_eq!(
+            concentration_weights(&[I80F48::ZERO, I80F48::ZERO]).unwrap(),
+            vec![I80F48::ZERO; 2]
+        );
+        assert!(concentration_weights(&[]).unwrap().is_empty());
+        assert!(concentration_weights(&[I80F48!(100), I80F48!(-1)]).is_err());
+    }
+
+    #[test]
+    fn max_ltv_stable_and_volatile() {
+        // Stablecoin pair
+        assert_eq_with_tolerance!(
+            max_ltv(I80F48!(0.9), I80F48!(1.05)),
+            I80F48!(0.857142),
+            I80F48!(0.000001)
+        );
+        // Volatile collateral
+        assert_eq_with_tolerance!(
+            max_ltv(I80F48!(0.65), I80F48!(1.25)),
+            I80F48!(0.52),
+            I80F48!(0.000001)
+        );
+        // Clamped to [0, 1]
+        assert_eq!(max_ltv(I80F48!(1), I80F48!(0.9)), I80F48::ONE);
+        assert_eq!(max_ltv(I80F48!(-0.1), I80F48!(1)), I80F48::ZERO);
+        assert_eq!(max_ltv(I80F48!(0.8), I80F48::ZERO), I80F48::ZERO);
+    }
+
+    #[test]
+    fn max_liquidation_amount_close_factor() {
+        assert_eq!(
+            max_liquidation_amount(I80F48!(1_000), I80F48!(0.5)).unwrap(),
+            I80F48!(500)
+        );
+        assert_eq!(
+            max_liquidation_amount(I80F48!(1_000), I80F48::ONE).unwrap(),
+            I80F48!(1_000)
+        );
+
+        assert!(max_liquidation_amount(I80F48!(1_000), I80F48::ZERO).is_err());
+        assert!(max_liquidation_amount(I80F48!(1_000), I80F48!(1.01)).is_err());
+    }
+
+    #[test]
+    fn convert_value_between_6_and_9_decimal_mints() {
+        // 1_000 USDC (6 decimals, $1) is 10 SOL (9 decimals, $100)
+        let sol = convert_value(I80F48!(1_000_000_000), I80F48::ONE, 6, I80F48!(100), 9).unwrap();
+        assert_eq!(sol, I80F48!(10_000_000_000));
+
+        // And back
+        let usdc = convert_value(sol, I80F48!(100), 9, I80F48::ONE, 6).unwrap();
+        assert_eq!(usdc, I80F48!(1_000_000_000));
+
+        // Fractional result: 1 USDC is 0.01 SOL
+        assert_eq_with_tolerance!(
+            convert_value(I80F48!(1_000_000), I80F48::ONE, 6, I80F48!(100), 9).unwrap(),
+            I80F48!(10_000_000),
+            I80F48!(0.000001)
+        );
+
+        assert!(convert_value(I80F48::ONE, I80F48::ONE, 6, I80F48::ZERO, 9).is_err());
+        assert!(convert_value(I80F48::ONE, I80F48::ONE, 6, I80F48::ONE, 24).is_err());
+    }
+
+    #[test]
+    fn min_profitable_incentive_small_and_large_seizures() {
+        // $0.50 of gas on a $1M seizure needs almost no discount
+        let large = min_profitable_incentive(I80F48!(0.5), I80F48!(1_000_000)).unwrap();
+        assert_eq_with_tolerance!(large, I80F48!(0.0000005), I80F48!(0.0000000001));
+        // The discount exactly covers gas
+        assert_eq_with_tolerance!(
+            I80F48!(1_000_000) - repay_for_seizure(I80F48!(1_000_000), large).unwrap(),
+            I80F48!(0.5),
+            I80F48!(0.000001)
+        );
+
+        // $0.50 of gas on a $10 seizure needs a 5% discount
+        let small = min_profitable_incentive(I80F48!(0.5), I80F48!(10)).unwrap();
+        assert_eq_with_tolerance!(small, I80F48!(0.05), I80F48!(0.000001));
+
+        // Dust seizures can't be made profitable
+        assert!(min_profitable_incentive(I80F48!(0.5), I80F48!(0.25)).unwrap() >= I80F48::ONE);
+
+        assert!(min_profitable_incentive(I80F48!(0.5), I80F48::ZERO).is_err());
+        assert!(min_profitable_incentive(I80F48!(-0.5), I80F48!(10)).is_err());
+    }
+}
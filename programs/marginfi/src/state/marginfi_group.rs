@@ -1,20 +1,27 @@
 use crate::borsh::{BorshDeserialize, BorshSerialize};
 use crate::constants::{
-    ASSET_TAG_DEFAULT, CLOSE_ENABLED_FLAG, EMISSION_FLAGS, FEE_VAULT_AUTHORITY_SEED,
-    FEE_VAULT_SEED, FREEZE_SETTINGS, GROUP_FLAGS, INSURANCE_VAULT_AUTHORITY_SEED,
-    INSURANCE_VAULT_SEED, LIQUIDITY_VAULT_AUTHORITY_SEED, LIQUIDITY_VAULT_SEED, MAX_ORACLE_KEYS,
-    MAX_PYTH_ORACLE_AGE, ORACLE_MIN_AGE, PERMISSIONLESS_BAD_DEBT_SETTLEMENT_FLAG,
-    PYTH_PUSH_MIGRATED, SECONDS_PER_YEAR, TOTAL_ASSET_VALUE_INIT_LIMIT_INACTIVE,
+    ASSET_TAG_DEFAULT, ASSET_TAG_SOL, BORROW_LIMIT_WITHIN_DEPOSIT_LIMIT, CAPABILITY_ADMIN,
+    CAPABILITY_EMODE_ADMIN, CLOSE_ENABLED_FLAG, EMISSION_FLAGS, EMISSION_FLAG_LENDING_ACTIVE,
+    EXP_10_I80F48, FEE_VAULT_AUTHORITY_SEED, FEE_VAULT_SEED, FREEZE_SETTINGS, GROUP_FLAGS,
+    INSURANCE_VAULT_AUTHORITY_SEED, INSURANCE_VAULT_SEED, LIQUIDITY_VAULT_AUTHORITY_SEED,
+    LIQUIDITY_VAULT_SEED, MAX_ACCRUAL_INTERVAL, MAX_DEPOSIT_FEE, MAX_ORACLE_KEYS,
+    MAX_PYTH_ORACLE_AGE, MAX_WITHDRAWAL_FEE, ORACLE_MIN_AGE,
+    PERMISSIONLESS_BAD_DEBT_SETTLEMENT_FLAG, PROGRAM_FEE_OVERRIDE, PYTH_PUSH_MIGRATED,
+    SECONDS_PER_YEAR, TOTAL_ASSET_VALUE_INIT_LIMIT_INACTIVE,
 };
 use crate::errors::MarginfiError;
 use crate::events::{GroupEventHeader, LendingPoolBankAccrueInterestEvent};
 use crate::prelude::MarginfiResult;
 use crate::set_if_some;
 use crate::state::bank_cache::{BankCache, ComputedInterestRates};
-use crate::state::emode::EmodeSettings;
-use crate::state::marginfi_account::{calc_value, BalanceSide, RequirementType};
+use crate::state::emode::{EmodeSettings, EMODE_TAG_EMPTY};
+use crate::state::marginfi_account::{
+    calc_value, convert_value, decimal_scale_factor, liquidation_price, max_liquidation_amount,
+    max_ltv, BalanceSide, RequirementType,
+};
 use crate::state::price::{OraclePriceFeedAdapter, OracleSetup};
-use crate::{assert_struct_align, assert_struct_size, check};
+use crate::utils::{i80f48_to_u64_checked, NumTraitsWithTolerance};
+use crate::{assert_struct_align, assert_struct_size, check, check_eq};
 use crate::{debug, math_error};
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::*;
@@ -26,6 +33,7 @@ use type_layout::TypeLayout;
 
 pub const PROGRAM_FEES_ENABLED: u64 = 1;
 pub const ARENA_GROUP: u64 = 2;
+pub const GROUP_PAUSED_FLAG: u64 = 4;
 
 assert_struct_size!(MarginfiGroup, 1056);
 #[account(zero_copy)]
@@ -36,7 +44,8 @@ pub struct MarginfiGroup {
     /// Bitmask for group settings flags.
     /// * 0: `PROGRAM_FEES_ENABLED` If set, program-level fees are enabled.
     /// * 1: `ARENA_GROUP` If set, this is an arena group, which can only have two banks
-    /// * Bits 1-63: Reserved for future use.
+    /// * 2: `GROUP_PAUSED_FLAG` If set, every bank in the group is treated as paused
+    /// * Bits 3-63: Reserved for future use.
     pub group_flags: u64,
     /// Caches information from the global `FeeState` so the FeeState can be omitted on certain ixes
     pub fee_state_cache: FeeStateCache,
@@ -60,6 +69,8 @@ pub struct MarginfiGroup {
     /// for every bank under this group
     pub delegate_emissions_admin: Pubkey,
 
+    /// The first `size_of::<GroupExtension>()` bytes are read through `extension` /
+    /// `extension_mut`, the rest is reserved.
     pub _padding_0: [[u64; 2]; 18],
     pub _padding_1: [[u64; 2]; 32],
     pub _padding_4: u64,
@@ -90,6 +101,25 @@ impl MarginfiGroup {
         }
     }
 
+    /// Bitmask of the roles `caller` holds in this group: `CAPABILITY_ADMIN` if it is the `admin`,
+    /// `CAPABILITY_EMODE_ADMIN` if it is the `emode_admin`. The default key never holds a role,
+    /// since an unset admin is stored as the default key.
+    pub fn caller_capabilities(&self, caller: Pubkey) -> u64 {
+        if caller == Pubkey::default() {
+            return 0;
+        }
+
+        let mut capabilities = 0;
+        if caller == self.admin {
+            capabilities |= CAPABILITY_ADMIN;
+        }
+        if caller == self.emode_admin {
+            capabilities |= CAPABILITY_EMODE_ADMIN;
+        }
+
+        capabilities
+    }
+
     pub fn update_curve_admin(&mut self, new_curve_admin: Pubkey) {
         if self.delegate_curve_admin == new_curve_admin {
             msg!("No change to curve admin: {:?}", new_curve_admin);
@@ -142,7 +172,7 @@ impl MarginfiGroup {
 
     pub fn get_group_bank_config(&self) -> GroupBankConfig {
         GroupBankConfig {
-            program_fees: self.group_flags == PROGRAM_FEES_ENABLED,
+            program_fees: self.program_fees_enabled(),
         }
     }
 
@@ -186,6 +216,85 @@ impl MarginfiGroup {
         (self.group_flags & ARENA_GROUP) != 0
     }
 
+    /// Emergency switch: while set, every bank in the group behaves as `Paused` regardless of its
+    /// own operational state.
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused {
+            self.group_flags |= GROUP_PAUSED_FLAG;
+        } else {
+            self.group_flags &= !GROUP_PAUSED_FLAG;
+        }
+    }
+
+    /// True if the group-wide pause is active
+    pub fn is_paused(&self) -> bool {
+        (self.group_flags & GROUP_PAUSED_FLAG) != 0
+    }
+
+    /// Start rotating `fee_state_cache.global_fee_wallet` to `new_wallet`. Replaces any proposal
+    /// already pending and restarts its timelock.
+    pub fn propose_fee_wallet(&mut self, new_wallet: Pubkey, now: i64) {
+        msg!(
+            "Proposed fee wallet {:?} (current {:?}) at {}",
+            new_wallet,
+            self.fee_state_cache.global_fee_wallet,
+            now
+        );
+        let ext = self.extension_mut();
+        ext.pending_fee_wallet = new_wallet;
+        ext.pending_fee_wallet_proposed_at = now;
+    }
+
+    /// Apply the pending fee wallet, if at least `min_delay` seconds have passed since it was
+    /// proposed. Errors with `TimelockNotElapsed` if called too early.
+    pub fn commit_fee_wallet(&mut self, now: i64, min_delay: i64) -> MarginfiResult {
+        let GroupExtension {
+            pending_fee_wallet,
+            pending_fee_wallet_proposed_at,
+            ..
+        } = *self.extension();
+        check!(
+            pending_fee_wallet != Pubkey::default(),
+            MarginfiError::InvalidConfig,
+            "No fee wallet change is pending"
+        );
+
+        let elapsed = now
+            .checked_sub(pending_fee_wallet_proposed_at)
+            .ok_or_else(math_error!())?;
+        check!(
+            elapsed >= min_delay,
+            MarginfiError::TimelockNotElapsed,
+            "Fee wallet proposed {}s ago, must wait {}s",
+            elapsed,
+            min_delay
+        );
+
+        msg!(
+            "Set fee wallet from {:?} to {:?}",
+            self.fee_state_cache.global_fee_wallet,
+            pending_fee_wallet
+        );
+        self.fee_state_cache.global_fee_wallet = pending_fee_wallet;
+        let ext = self.extension_mut();
+        ext.pending_fee_wallet = Pubkey::default();
+        ext.pending_fee_wallet_proposed_at = 0;
+
+        Ok(())
+    }
+
+    /// Typed view of the extension fields stored at the start of `_padding_0`
+    pub fn extension(&self) -> &GroupExtension {
+        let bytes: &[u8] = bytemuck::cast_slice(&self._padding_0);
+        bytemuck::from_bytes(&bytes[..std::mem::size_of::<GroupExtension>()])
+    }
+
+    /// Mutable typed view of the extension fields stored at the start of `_padding_0`
+    pub fn extension_mut(&mut self) -> &mut GroupExtension {
+        let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut self._padding_0);
+        bytemuck::from_bytes_mut(&mut bytes[..std::mem::size_of::<GroupExtension>()])
+    }
+
     // Increment the bank count by 1. If this is an arena group, which only supports two banks,
     // errors if trying to add a third bank. If you managed to create 16,000 banks, congrats, does
     // nothing.
@@ -202,6 +311,33 @@ impl MarginfiGroup {
     }
 }
 
+/// Named fields overlaid on the start of `MarginfiGroup::_padding_0`, so new group-level state can
+/// be added without changing the account layout. Groups created before a field existed read it as
+/// zero, so every field must treat zero as "unset".
+///
+/// Layout (byte offsets into `_padding_0`):
+/// * 0..32: `pending_fee_wallet`
+/// * 32..40: `pending_fee_wallet_proposed_at`
+/// * 40..48: reserved
+///
+/// New fields must be appended in place of `_reserved` or after it, never reordered.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Zeroable, Pod, PartialEq, Eq)]
+pub struct GroupExtension {
+    /// Fee wallet proposed via `propose_fee_wallet`, awaiting `commit_fee_wallet`. Default (all
+    /// zeroes) if nothing is pending.
+    pub pending_fee_wallet: Pubkey,
+    /// Unix timestamp at which `pending_fee_wallet` was proposed
+    pub pending_fee_wallet_proposed_at: i64,
+    pub _reserved: [u8; 8],
+}
+
+assert_struct_size!(GroupExtension, 48);
+assert_struct_align!(GroupExtension, 8);
+static_assertions::const_assert!(
+    std::mem::size_of::<GroupExtension>() <= std::mem::size_of::<[[u64; 2]; 18]>()
+);
+
 #[derive(
     AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Zeroable, Pod, Debug, PartialEq, Eq,
 )]
@@ -361,7 +497,16 @@ pub struct Bank {
     ///   the bank may safely be closed if this is zero. Will never go negative.
     pub borrowing_position_count: i32,
     pub _padding_0: [u8; 16],
-    pub _padding_1: [[u64; 2]; 19], // 8 * 2 * 19 = 304B
+    /// Total fees ever collected for the `insurance_vault`. Unlike
+    /// `collected_insurance_fees_outstanding`, never reset when fees are harvested.
+    pub lifetime_insurance_fees: WrappedI80F48,
+    /// Total fees ever collected for the `fee_vault`. Unlike `collected_group_fees_outstanding`,
+    /// never reset when fees are harvested.
+    pub lifetime_group_fees: WrappedI80F48,
+    /// Total fees ever collected for the `FeeState.global_fee_wallet`. Unlike
+    /// `collected_program_fees_outstanding`, never reset when fees are harvested.
+    pub lifetime_program_fees: WrappedI80F48,
+    pub _padding_1: [[u64; 2]; 16], // 8 * 2 * 16 = 256B
 }
 
 // Initialize a Bank instance
@@ -434,18 +579,54 @@ impl Bank {
             .ok_or_else(math_error!())?)
     }
 
+    /// Value of a liability of `shares` at `price`, weighted by `liability_weight_maint`, i.e. what
+    /// the position counts for in the maintenance (liquidation) check.
+    pub fn calc_weighted_liability_maint(
+        &self,
+        shares: I80F48,
+        price: I80F48,
+    ) -> MarginfiResult<I80F48> {
+        let amount = self.get_liability_amount(shares)?;
+
+        calc_value(
+            amount,
+            price,
+            self.mint_decimals,
+            Some(self.config.liability_weight_maint.into()),
+        )
+    }
+
     // Convert an amount (such as the new loan amount) into the current loan share (liability shares) that should be obtained
     pub fn get_liability_shares(&self, value: I80F48) -> MarginfiResult<I80F48> {
-        Ok(value
-            .checked_div(self.liability_share_value.into())
-            .ok_or_else(math_error!())?)
+        shares_at_value(value, self.liability_share_value.into())
     }
 
     // Convert a deposit amount into the current deposit share (asset shares) that should be obtained
     pub fn get_asset_shares(&self, value: I80F48) -> MarginfiResult<I80F48> {
-        Ok(value
-            .checked_div(self.asset_share_value.into())
-            .ok_or_else(math_error!())?)
+        shares_at_value(value, self.asset_share_value.into())
+    }
+
+    /// Smallest deposit (in whole native units) that mints a non-zero amount of asset shares.
+    /// Anything smaller rounds to zero shares and would be lost, so deposits below this should be
+    /// rejected with `DepositTooSmall`.
+    pub fn min_viable_deposit(&self) -> MarginfiResult<I80F48> {
+        let asset_share_value: I80F48 = self.asset_share_value.into();
+        // The smallest representable share amount is `I80F48::DELTA`
+        let min_amount = asset_share_value
+            .checked_mul(I80F48::DELTA)
+            .ok_or_else(math_error!())?
+            .checked_ceil()
+            .ok_or_else(math_error!())?
+            .max(I80F48::ONE);
+
+        // `checked_mul` truncates, so step up if the product was rounded below the threshold
+        if self.get_asset_shares(min_amount)? == I80F48::ZERO {
+            return Ok(min_amount
+                .checked_add(I80F48::ONE)
+                .ok_or_else(math_error!())?);
+        }
+
+        Ok(min_amount)
     }
 
     // updating the total_asset_shares of a Bank, check whether the deposit limit has been exceeded.
@@ -477,48 +658,282 @@ impl Bank {
         Ok(())
     }
 
+    /// Fee charged on a deposit of `amount`, to be deducted before minting shares.
+    pub fn calc_deposit_fee(&self, amount: I80F48) -> MarginfiResult<I80F48> {
+        Ok(amount
+            .checked_mul(self.config.get_deposit_fee())
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Fee charged on a withdrawal of `amount`. The user receives `amount - fee`.
+    pub fn calc_withdrawal_fee(&self, amount: I80F48) -> MarginfiResult<I80F48> {
+        Ok(amount
+            .checked_mul(self.config.get_withdrawal_fee())
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Tokens a user actually receives per asset share when withdrawing, i.e. `asset_share_value *
+    /// (1 - withdrawal_fee)`. Just `asset_share_value` if there is no withdrawal fee.
+    pub fn withdrawal_share_value(&self) -> MarginfiResult<I80F48> {
+        let asset_share_value: I80F48 = self.asset_share_value.into();
+        let withdrawal_fee = self.config.get_withdrawal_fee();
+        if withdrawal_fee == I80F48::ZERO {
+            return Ok(asset_share_value);
+        }
+
+        Ok(asset_share_value
+            .checked_mul(I80F48::ONE - withdrawal_fee)
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Charges the withdrawal fee on `amount`, crediting it to the insurance fees outstanding (the
+    /// fee stays in the liquidity vault until harvested into the `insurance_vault`). Returns the
+    /// fee charged.
+    pub fn charge_withdrawal_fee(&mut self, amount: I80F48) -> MarginfiResult<I80F48> {
+        let fee = self.calc_withdrawal_fee(amount)?;
+        if fee == I80F48::ZERO {
+            return Ok(fee);
+        }
+
+        self.collected_insurance_fees_outstanding = {
+            fee.checked_add(self.collected_insurance_fees_outstanding.into())
+                .ok_or_else(math_error!())?
+                .into()
+        };
+        self.lifetime_insurance_fees = {
+            fee.checked_add(self.lifetime_insurance_fees.into())
+                .ok_or_else(math_error!())?
+                .into()
+        };
+
+        Ok(fee)
+    }
+
     // A Bank configures the "initial margin USD limit" (total_asset_value_init_limit),
     // a discount factor is dynamically given to reduce the weight of the asset in the initial margin calculation.
     pub fn maybe_get_asset_weight_init_discount(
         &self,
         price: I80F48,
     ) -> MarginfiResult<Option<I80F48>> {
-        if self.config.usd_init_limit_active() {
-            // Calculate the current dollar value of all bank deposits
-            let bank_total_assets_value = calc_value(
-                self.get_asset_amount(self.total_asset_shares.into())?,
-                price,
-                self.mint_decimals,
-                None,
-            )?;
+        if !self.config.usd_init_limit_active() {
+            return Ok(None);
+        }
+
+        // Calculate the current dollar value of all bank deposits
+        let bank_total_assets_value = calc_value(
+            self.get_asset_amount(self.total_asset_shares.into())?,
+            price,
+            self.mint_decimals,
+            None,
+        )?;
+
+        self.group_discount_factor(bank_total_assets_value)
+    }
+
+    /// The `total_asset_value_init_limit` discount (see `maybe_get_asset_weight_init_discount`)
+    /// measured against `group_total_value`, e.g. the USD value of this asset across every bank
+    /// in the group, rather than this bank's deposits alone. `None` if the limit is inactive or
+    /// not exceeded.
+    pub fn group_discount_factor(
+        &self,
+        group_total_value: I80F48,
+    ) -> MarginfiResult<Option<I80F48>> {
+        if !self.config.usd_init_limit_active() {
+            return Ok(None);
+        }
+
+        let total_asset_value_init_limit =
+            I80F48::from_num(self.config.total_asset_value_init_limit);
+
+        #[cfg(target_os = "solana")]
+        debug!(
+            "Init limit active, limit: {}, total_assets: {}",
+            total_asset_value_init_limit, group_total_value
+        );
 
-            let total_asset_value_init_limit =
-                I80F48::from_num(self.config.total_asset_value_init_limit);
+        if group_total_value > total_asset_value_init_limit {
+            let discount = total_asset_value_init_limit
+                .checked_div(group_total_value)
+                .ok_or_else(math_error!())?;
 
             #[cfg(target_os = "solana")]
             debug!(
-                "Init limit active, limit: {}, total_assets: {}",
-                total_asset_value_init_limit, bank_total_assets_value
+                "Discounting assets by {:.2} because of total deposits {} over {} use cap",
+                discount, group_total_value, total_asset_value_init_limit
             );
 
-            if bank_total_assets_value > total_asset_value_init_limit {
-                let discount = total_asset_value_init_limit
-                    .checked_div(bank_total_assets_value)
-                    .ok_or_else(math_error!())?;
+            Ok(Some(discount))
+        } else {
+            Ok(None)
+        }
+    }
 
-                #[cfg(target_os = "solana")]
-                debug!(
-                    "Discounting assets by {:.2} because of total deposits {} over {} use cap",
-                    discount, bank_total_assets_value, total_asset_value_init_limit
-                );
+    /// The tighter of the bank's two deposit caps, in native token units: `deposit_limit`, and
+    /// `total_asset_value_init_limit` (in USD) converted to tokens at `price`. An inactive limit
+    /// doesn't bind, `u64::MAX` if neither is active. Errors with `MathError` if the USD limit is
+    /// active and `price` is not positive or `mint_decimals` is out of range.
+    ///
+    /// Note: deposits past the USD limit are not rejected, they only dilute the init asset weight
+    /// (see `maybe_get_asset_weight_init_discount`). Past this cap, new deposits add no borrowing
+    /// power.
+    pub fn effective_deposit_cap(&self, price: I80F48) -> MarginfiResult<I80F48> {
+        let deposit_cap = I80F48::from_num(self.config.deposit_limit);
+        if !self.config.usd_init_limit_active() {
+            return Ok(deposit_cap);
+        }
+        check!(price > I80F48::ZERO, MarginfiError::MathError);
 
-                Ok(Some(discount))
-            } else {
-                Ok(None)
-            }
+        let usd_cap = I80F48::from_num(self.config.total_asset_value_init_limit)
+            .checked_mul(decimal_scale_factor(self.mint_decimals)?)
+            .ok_or_else(math_error!())?
+            .checked_div(price)
+            .ok_or_else(math_error!())?;
+
+        Ok(deposit_cap.min(usd_cap))
+    }
+
+    /// The init asset weight after applying the `total_asset_value_init_limit` discount (if
+    /// active), as an f64 for rendering in UIs.
+    ///
+    /// Note: the conversion to f64 loses precision, this is for display only and must never be
+    /// used in health calculations.
+    pub fn display_asset_weight_init(&self, price: I80F48) -> MarginfiResult<f64> {
+        let asset_weight_init: I80F48 = self.config.asset_weight_init.into();
+        let weight = match self.maybe_get_asset_weight_init_discount(price)? {
+            Some(discount) => asset_weight_init
+                .checked_mul(discount)
+                .ok_or_else(math_error!())?,
+            None => asset_weight_init,
+        };
+
+        Ok(weight.to_num::<f64>())
+    }
+
+    /// The `(asset_weight, liability_weight)` for `req_type` when this bank's emode config is
+    /// applied to collateral with emode `collateral_tag`. If emode is enabled and an entry matches
+    /// the tag, its weights replace the bank's where more generous (a higher asset weight, a lower
+    /// liability weight). Emode never tightens the bank's weights, and entries without liability
+    /// weights leave the liability weight untouched.
+    pub fn resolve_weights(
+        &self,
+        req_type: RequirementType,
+        collateral_tag: u16,
+    ) -> (I80F48, I80F48) {
+        let (asset_weight, liability_weight) = self.config.get_weights(req_type);
+        if !self.emode.is_enabled() {
+            return (asset_weight, liability_weight);
+        }
+        let Some(entry) = self.emode.emode_config.find_with_tag(collateral_tag) else {
+            return (asset_weight, liability_weight);
+        };
+
+        let (emode_asset_weight, emode_liability_weight): (I80F48, I80F48) = match req_type {
+            RequirementType::Initial => (
+                entry.asset_weight_init.into(),
+                entry.liability_weight_init(),
+            ),
+            RequirementType::Maintenance => (
+                entry.asset_weight_maint.into(),
+                entry.liability_weight_maint(),
+            ),
+            RequirementType::Equity => return (asset_weight, liability_weight),
+        };
+
+        let liability_weight = if entry.has_liability_weights() {
+            liability_weight.min(emode_liability_weight)
         } else {
-            Ok(None)
+            liability_weight
+        };
+
+        (asset_weight.max(emode_asset_weight), liability_weight)
+    }
+
+    /// Extra borrowing power emode gives `collateral_shares` of collateral with emode
+    /// `collateral_tag` at `price`, i.e. the collateral's value at the emode init asset weight (see
+    /// `resolve_weights`) minus its value at the base init asset weight. Zero when emode doesn't
+    /// apply.
+    pub fn emode_borrow_power_delta(
+        &self,
+        collateral_shares: I80F48,
+        price: I80F48,
+        collateral_tag: u16,
+    ) -> MarginfiResult<I80F48> {
+        let base_weight: I80F48 = self.config.asset_weight_init.into();
+        let (emode_weight, _) = self.resolve_weights(RequirementType::Initial, collateral_tag);
+        if emode_weight == base_weight {
+            return Ok(I80F48::ZERO);
+        }
+
+        let amount = self.get_asset_amount(collateral_shares)?;
+        let emode_value = calc_value(amount, price, self.mint_decimals, Some(emode_weight))?;
+        let base_value = calc_value(amount, price, self.mint_decimals, Some(base_weight))?;
+
+        Ok(emode_value
+            .checked_sub(base_value)
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Price of this bank's collateral at which a position becomes liquidatable (see
+    /// `liquidation_price`), using the maint weights after emode for `collateral_tag` is applied
+    /// (see `resolve_weights`). Emode can only relax the weights, so the result is never above the
+    /// price without emode.
+    pub fn emode_adjusted_liquidation_price(
+        &self,
+        collateral_amount: I80F48,
+        collateral_decimals: u8,
+        liability_value: I80F48,
+        collateral_tag: u16,
+    ) -> MarginfiResult<I80F48> {
+        let (asset_weight_maint, liability_weight_maint) =
+            self.resolve_weights(RequirementType::Maintenance, collateral_tag);
+
+        liquidation_price(
+            collateral_amount,
+            collateral_decimals,
+            asset_weight_maint,
+            liability_value,
+            liability_weight_maint,
+        )
+    }
+
+    /// The init asset weight to apply to collateral with emode `collateral_tag`, composing both
+    /// weight adjustments. Order of operations:
+    /// 1. Resolve the base weight: `config.asset_weight_init`, relaxed by emode (see
+    ///    `resolve_weights`).
+    /// 2. Multiply by the `total_asset_value_init_limit` discount, if active and exceeded at `price`.
+    pub fn final_asset_weight_init(
+        &self,
+        price: I80F48,
+        collateral_tag: u16,
+    ) -> MarginfiResult<I80F48> {
+        let (weight, _) = self.resolve_weights(RequirementType::Initial, collateral_tag);
+
+        match self.maybe_get_asset_weight_init_discount(price)? {
+            Some(discount) => Ok(weight.checked_mul(discount).ok_or_else(math_error!())?),
+            None => Ok(weight),
+        }
+    }
+
+    /// What `collateral_shares` of this bank's collateral with emode `collateral_tag` add to an
+    /// account's cross-margin health at `price`: its value at `final_asset_weight_init` (base or
+    /// emode weight, discounted by the init limit if exceeded). Zero for `RiskTier::Isolated`
+    /// banks, which never count as collateral for other positions, except in an arena group where
+    /// every bank backs the other.
+    pub fn cross_margin_collateral_value(
+        &self,
+        collateral_shares: I80F48,
+        price: I80F48,
+        collateral_tag: u16,
+        group: &MarginfiGroup,
+    ) -> MarginfiResult<I80F48> {
+        if self.config.risk_tier == RiskTier::Isolated && !self.is_arena_bank(group) {
+            return Ok(I80F48::ZERO);
         }
+
+        let weight = self.final_asset_weight_init(price, collateral_tag)?;
+        let amount = self.get_asset_amount(collateral_shares)?;
+
+        calc_value(amount, price, self.mint_decimals, Some(weight))
     }
 
     // Update the Bank's total liability shares and check the borrow limit
@@ -549,6 +964,39 @@ impl Bank {
         Ok(())
     }
 
+    /// `(total_asset_shares, total_liability_shares)`. Off-chain, each should equal the sum of
+    /// the matching shares over every balance in the bank.
+    pub fn share_totals(&self) -> (I80F48, I80F48) {
+        (
+            self.total_asset_shares.into(),
+            self.total_liability_shares.into(),
+        )
+    }
+
+    /// Errors with `MarginfiError::NegativeShareTotal` if either share total is negative, which
+    /// only happens if the bank's accounting is corrupted.
+    pub fn assert_shares_non_negative(&self) -> MarginfiResult {
+        let (total_asset_shares, total_liability_shares) = self.share_totals();
+        check!(
+            total_asset_shares >= I80F48::ZERO && total_liability_shares >= I80F48::ZERO,
+            MarginfiError::NegativeShareTotal,
+            "asset shares: {}, liability shares: {}",
+            total_asset_shares,
+            total_liability_shares
+        );
+
+        Ok(())
+    }
+
+    /// True if the bank has no oracle (`OracleSetup::None`) but still holds deposits or
+    /// liabilities, which then cannot be priced for health checks or liquidations.
+    pub fn is_pricing_broken(&self) -> bool {
+        let (total_asset_shares, total_liability_shares) = self.share_totals();
+
+        self.config.oracle_setup == OracleSetup::None
+            && (total_asset_shares != I80F48::ZERO || total_liability_shares != I80F48::ZERO)
+    }
+
     // Check whether the bank's "total assets ≥ total liabilities" is true to prevent illegal capital utilization ratio
     pub fn check_utilization_ratio(&self) -> MarginfiResult {
         let total_assets = self.get_asset_amount(self.total_asset_shares.into())?;
@@ -564,108 +1012,574 @@ impl Bank {
         Ok(())
     }
 
-    pub fn configure(&mut self, config: &BankConfigOpt) -> MarginfiResult {
-        set_if_some!(self.config.asset_weight_init, config.asset_weight_init);
-        set_if_some!(self.config.asset_weight_maint, config.asset_weight_maint);
-        set_if_some!(
-            self.config.liability_weight_init,
-            config.liability_weight_init
-        );
-        set_if_some!(
-            self.config.liability_weight_maint,
-            config.liability_weight_maint
-        );
-        set_if_some!(self.config.deposit_limit, config.deposit_limit);
-        set_if_some!(self.config.borrow_limit, config.borrow_limit);
-        set_if_some!(self.config.operational_state, config.operational_state);
+    /// True if liabilities exceed assets (e.g. after bad debt), i.e. `check_utilization_ratio`
+    /// would fail. Read-only, keepers can use this to decide when to pause the bank.
+    pub fn should_auto_pause(&self) -> MarginfiResult<bool> {
+        let total_assets = self.get_asset_amount(self.total_asset_shares.into())?;
+        let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
 
-        if let Some(ir_config) = &config.interest_rate_config {
-            self.config.interest_rate_config.update(ir_config);
-        }
+        Ok(total_assets < total_liabilities)
+    }
 
-        set_if_some!(self.config.risk_tier, config.risk_tier);
-        set_if_some!(self.config.asset_tag, config.asset_tag);
-        set_if_some!(
-            self.config.total_asset_value_init_limit,
-            config.total_asset_value_init_limit
-        );
-        set_if_some!(self.config.oracle_max_age, config.oracle_max_age);
+    /// Liabilities not backed by any asset, i.e. `total_liabilities - total_assets`, floored at
+    /// zero.
+    pub fn bad_debt(&self) -> MarginfiResult<I80F48> {
+        let total_assets = self.get_asset_amount(self.total_asset_shares.into())?;
+        let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
 
-        if let Some(flag) = config.permission_bad_debt_settlement {
-            msg!(
-                "setting bad debt settlement: {:?}",
-                config.permission_bad_debt_settlement
-            );
-            self.update_flag(flag, PERMISSIONLESS_BAD_DEBT_SETTLEMENT_FLAG);
-        }
+        Ok(total_liabilities
+            .checked_sub(total_assets)
+            .ok_or_else(math_error!())?
+            .max(I80F48::ZERO))
+    }
 
-        if let Some(flag) = config.freeze_settings {
-            msg!(
-                "setting freeze settings: {:?}",
-                config.freeze_settings.unwrap()
-            );
-            self.update_flag(flag, FREEZE_SETTINGS);
-        }
+    /// Tokens not currently lent out, i.e. `total_assets - total_liabilities`, floored at zero.
+    /// Withdrawals larger than this cannot be served by the liquidity vault.
+    pub fn available_liquidity(&self) -> MarginfiResult<I80F48> {
+        let total_assets = self.get_asset_amount(self.total_asset_shares.into())?;
+        let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
 
-        self.config.validate()?;
+        Ok(total_assets
+            .checked_sub(total_liabilities)
+            .ok_or_else(math_error!())?
+            .max(I80F48::ZERO))
+    }
 
-        Ok(())
+    /// Interest that can still accrue on liabilities before the bank becomes insolvent, i.e.
+    /// `total_assets - total_liabilities` floored at zero. The same quantity as
+    /// `available_liquidity`, viewed as an early warning: near zero means little buffer is left.
+    pub fn insolvency_headroom(&self) -> MarginfiResult<I80F48> {
+        self.available_liquidity()
     }
 
-    // Configures just the borrow and deposit limits, ignoring all other values
-    pub fn configure_unfrozen_fields_only(&mut self, config: &BankConfigOpt) -> MarginfiResult {
-        set_if_some!(self.config.deposit_limit, config.deposit_limit);
-        set_if_some!(self.config.borrow_limit, config.borrow_limit);
+    /// True if `amount` can be withdrawn without exceeding `available_liquidity`. Withdrawals
+    /// that fail this check should be rejected with `MarginfiError::InsufficientLiquidity`.
+    pub fn can_withdraw(&self, amount: I80F48) -> MarginfiResult<bool> {
+        Ok(amount <= self.available_liquidity()?)
+    }
+
+    /// Errors with `MarginfiError::InsufficientLiquidity` unless liabilities stay fully covered
+    /// after `withdraw_amount` leaves the bank, i.e. `total_assets - withdraw_amount >=
+    /// total_liabilities` (see `can_withdraw`).
+    pub fn assert_solvent_after_withdraw(&self, withdraw_amount: I80F48) -> MarginfiResult {
+        check!(
+            self.can_withdraw(withdraw_amount)?,
+            MarginfiError::InsufficientLiquidity
+        );
 
         Ok(())
     }
 
-    /// Calculate the interest rate accrual state changes for a given time period
+    /// Most a liquidator can repay of this bank's liability in one liquidation seizing
+    /// `collateral_bank`'s collateral: the smaller of the close-factor cap (see
+    /// `max_liquidation_amount`) and what the collateral vault can release (see
+    /// `available_liquidity`), converted into this bank's tokens at the given prices.
     ///
-    /// Collected protocol and insurance fees are stored in state.
-    /// A separate instruction is required to withdraw these fees.
-    pub fn accrue_interest(
-        &mut self,
-        current_timestamp: i64,
-        group: &MarginfiGroup,
-        #[cfg(not(feature = "client"))] bank: Pubkey,
-    ) -> MarginfiResult<()> {
-        #[cfg(all(not(feature = "client"), feature = "debug"))]
-        anchor_lang::solana_program::log::sol_log_compute_units();
-
-        let time_delta: u64 = (current_timestamp - self.last_update).try_into().unwrap();
-        if time_delta == 0 {
-            return Ok(());
-        }
+    /// `liability_value` and the result are in this bank's native token units. Prices are per
+    /// whole token in the same quote currency, apply any liquidation discount to them beforehand.
+    pub fn max_liquidator_repay(
+        &self,
+        close_factor: I80F48,
+        liability_value: I80F48,
+        liability_price: I80F48,
+        collateral_bank: &Bank,
+        collateral_price: I80F48,
+    ) -> MarginfiResult<I80F48> {
+        let close_factor_cap = max_liquidation_amount(liability_value, close_factor)?;
+        let liquidity_cap = convert_value(
+            collateral_bank.available_liquidity()?,
+            collateral_price,
+            collateral_bank.mint_decimals,
+            liability_price,
+            self.mint_decimals,
+        )?;
+
+        Ok(close_factor_cap.min(liquidity_cap))
+    }
 
+    /// Current utilization ratio, i.e. `total_liabilities / total_assets`. Zero if the bank has no
+    /// deposits.
+    pub fn get_utilization_rate(&self) -> MarginfiResult<I80F48> {
         let total_assets = self.get_asset_amount(self.total_asset_shares.into())?;
         let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
 
-        self.last_update = current_timestamp;
+        if total_assets == I80F48::ZERO {
+            return Ok(I80F48::ZERO);
+        }
 
-        if (total_assets == I80F48::ZERO) || (total_liabilities == I80F48::ZERO) {
-            #[cfg(not(feature = "client"))]
-            emit!(LendingPoolBankAccrueInterestEvent {
-                header: GroupEventHeader {
-                    marginfi_group: self.group,
-                    signer: None
-                },
-                bank,
-                mint: self.mint,
-                delta: time_delta,
-                fees_collected: 0.,
-                insurance_collected: 0.,
+        Ok(total_liabilities
+            .checked_div(total_assets)
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Single qualitative status for display, the first that applies of:
+    /// * `Insolvent`: liabilities exceed assets (see `should_auto_pause`), or the totals cannot be
+    ///   computed
+    /// * `Paused` / `ReduceOnly`: the bank's own `operational_state`
+    /// * `NearCap`: deposits or borrows at or above `BankStatus::NEAR_CAP` of an active limit
+    /// * `HighUtilization`: utilization above the curve's `optimal_utilization_rate`
+    /// * `Healthy`
+    ///
+    /// Does not account for a group-level pause, see `effective_operational_state`.
+    pub fn status_badge(&self) -> BankStatus {
+        let totals = self
+            .get_asset_amount(self.total_asset_shares.into())
+            .and_then(|assets| {
+                Ok((
+                    assets,
+                    self.get_liability_amount(self.total_liability_shares.into())?,
+                ))
             });
+        let Ok((total_assets, total_liabilities)) = totals else {
+            return BankStatus::Insolvent;
+        };
 
-            return Ok(());
+        if total_assets < total_liabilities {
+            return BankStatus::Insolvent;
         }
-        let ir_calc = self
-            .config
-            .interest_rate_config
-            .create_interest_rate_calculator(group);
 
-        let InterestRateStateChanges {
-            new_asset_share_value: asset_share_value,
+        match self.config.operational_state {
+            BankOperationalState::Paused => return BankStatus::Paused,
+            BankOperationalState::ReduceOnly => return BankStatus::ReduceOnly,
+            BankOperationalState::Operational => {}
+        }
+
+        let near_cap = |total: I80F48, limit: u64| {
+            total >= I80F48::from_num(limit).saturating_mul(BankStatus::NEAR_CAP)
+        };
+        if (self.config.is_deposit_limit_active()
+            && near_cap(total_assets, self.config.deposit_limit))
+            || (self.config.is_borrow_limit_active()
+                && near_cap(total_liabilities, self.config.borrow_limit))
+        {
+            return BankStatus::NearCap;
+        }
+
+        let optimal_utilization: I80F48 = self
+            .config
+            .interest_rate_config
+            .optimal_utilization_rate
+            .into();
+        match self.get_utilization_rate() {
+            Ok(utilization) if utilization <= optimal_utilization => BankStatus::Healthy,
+            _ => BankStatus::HighUtilization,
+        }
+    }
+
+    /// `insurance_balance / total_liabilities`, how much of the outstanding debt the insurance fund
+    /// could absorb. `I80F48::MAX` if the bank has no liabilities.
+    pub fn insurance_coverage_ratio(&self, insurance_balance: I80F48) -> MarginfiResult<I80F48> {
+        let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
+
+        if total_liabilities == I80F48::ZERO {
+            return Ok(I80F48::MAX);
+        }
+
+        Ok(insurance_balance
+            .checked_div(total_liabilities)
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Deposit into the insurance vault needed to reach `target_ratio` coverage (see
+    /// `insurance_coverage_ratio`), i.e. `max(0, target_ratio * total_liabilities -
+    /// current_balance)`.
+    pub fn insurance_topup_needed(
+        &self,
+        current_balance: I80F48,
+        target_ratio: I80F48,
+    ) -> MarginfiResult<I80F48> {
+        if self.insurance_coverage_ratio(current_balance)? >= target_ratio {
+            return Ok(I80F48::ZERO);
+        }
+
+        let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
+        let target_balance = target_ratio
+            .checked_mul(total_liabilities)
+            .ok_or_else(math_error!())?;
+
+        Ok(target_balance
+            .checked_sub(current_balance)
+            .ok_or_else(math_error!())?
+            .max(I80F48::ZERO))
+    }
+
+    /// Share of deposits backing loans as collateral, i.e. `weighted_collateral_in_use /
+    /// total_assets`. `weighted_collateral_in_use` is in native token units, like the bank's total
+    /// assets. Distinct from `get_utilization_rate`, which measures deposits lent out. Zero if the
+    /// bank has no deposits.
+    pub fn collateral_utilization(
+        &self,
+        weighted_collateral_in_use: I80F48,
+    ) -> MarginfiResult<I80F48> {
+        let total_assets = self.get_asset_amount(self.total_asset_shares.into())?;
+
+        if total_assets == I80F48::ZERO {
+            return Ok(I80F48::ZERO);
+        }
+
+        Ok(weighted_collateral_in_use
+            .checked_div(total_assets)
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Interest rates at the bank's current utilization, using only the bank's own config.
+    ///
+    /// Note: program fees are set at the group level and are excluded here. They are charged to
+    /// borrowers on top of the base rate and never change the lending rate.
+    fn current_interest_rates(&self) -> MarginfiResult<ComputedInterestRates> {
+        let utilization_rate = self.get_utilization_rate()?;
+        let ir_calc = self
+            .config
+            .interest_rate_config
+            .create_interest_rate_calculator(&MarginfiGroup::default());
+
+        Ok(ir_calc
+            .calc_interest_rate(utilization_rate)
+            .ok_or_else(math_error!())?)
+    }
+
+    /// APR currently paid to depositors, based on the bank's utilization. Already capped at
+    /// `max_lending_rate` if set, see `InterestRateCalc::calc_interest_rate`.
+    pub fn effective_lending_apr(&self) -> MarginfiResult<I80F48> {
+        Ok(self.current_interest_rates()?.lending_rate_apr)
+    }
+
+    /// APR currently charged to borrowers (base rate plus group and insurance fees), based on the
+    /// bank's utilization.
+    pub fn effective_borrow_apr(&self) -> MarginfiResult<I80F48> {
+        Ok(self.current_interest_rates()?.borrowing_rate_apr)
+    }
+
+    /// Total cost of borrowing `amount` for `hold_seconds`: the one-time
+    /// `protocol_origination_fee` plus simple interest at the current `effective_borrow_apr`.
+    /// Assumes the rate stays the same for the whole hold.
+    pub fn effective_borrow_cost(
+        &self,
+        amount: I80F48,
+        hold_seconds: u64,
+    ) -> MarginfiResult<I80F48> {
+        let origination_fee = amount
+            .checked_mul(
+                self.config
+                    .interest_rate_config
+                    .protocol_origination_fee
+                    .into(),
+            )
+            .ok_or_else(math_error!())?;
+        let interest =
+            calc_interest_payment_for_period(self.effective_borrow_apr()?, hold_seconds, amount)
+                .ok_or_else(math_error!())?;
+
+        Ok(origination_fee
+            .checked_add(interest)
+            .ok_or_else(math_error!())?)
+    }
+
+    /// What a borrower actually receives when requesting `requested`: `requested` minus the
+    /// `protocol_origination_fee` and, if the group charges program fees (see
+    /// `program_fees_enabled`), the program fee rate (see `effective_program_fee`) on `requested`.
+    /// Floored at zero.
+    pub fn net_borrow_proceeds(
+        &self,
+        requested: I80F48,
+        group: &MarginfiGroup,
+    ) -> MarginfiResult<I80F48> {
+        let origination_fee = requested
+            .checked_mul(
+                self.config
+                    .interest_rate_config
+                    .protocol_origination_fee
+                    .into(),
+            )
+            .ok_or_else(math_error!())?;
+        let program_fee = if self.program_fees_enabled(&group.get_group_bank_config()) {
+            let (_, program_fee_rate) = self.effective_program_fee(group)?;
+            requested
+                .checked_mul(program_fee_rate)
+                .ok_or_else(math_error!())?
+        } else {
+            I80F48::ZERO
+        };
+
+        Ok(requested
+            .checked_sub(origination_fee)
+            .and_then(|net| net.checked_sub(program_fee))
+            .ok_or_else(math_error!())?
+            .max(I80F48::ZERO))
+    }
+
+    /// Split a depositor's earnings into `(interest_earned, emissions_earned)`, where interest is
+    /// `user_shares * (asset_share_value - prior_share_value)` and `emissions_earned` is passed
+    /// through as-is. Interest is negative if the share value fell, e.g. after a socialized loss.
+    pub fn depositor_earnings_breakdown(
+        &self,
+        user_shares: I80F48,
+        prior_share_value: I80F48,
+        emissions_earned: I80F48,
+    ) -> MarginfiResult<(I80F48, I80F48)> {
+        let interest_earned = I80F48::from(self.asset_share_value)
+            .checked_sub(prior_share_value)
+            .and_then(|delta| delta.checked_mul(user_shares))
+            .ok_or_else(math_error!())?;
+
+        Ok((interest_earned, emissions_earned))
+    }
+
+    /// Spread between what borrowers pay and what depositors earn, i.e. `effective_borrow_apr -
+    /// effective_lending_apr`.
+    ///
+    /// Note: the lending rate is scaled by utilization (interest is spread over all deposits), so
+    /// besides the group and insurance fees this includes `base_rate * (1 - utilization)`.
+    pub fn net_interest_margin(&self) -> MarginfiResult<I80F48> {
+        Ok(self
+            .effective_borrow_apr()?
+            .checked_sub(self.effective_lending_apr()?)
+            .ok_or_else(math_error!())?)
+    }
+
+    /// APY currently paid to depositors, i.e. `(1 + apr / n)^n - 1` where `apr` is the
+    /// `effective_lending_apr` and `n` is `compounds_per_year`.
+    ///
+    /// `n` is clamped to once per second (`SECONDS_PER_YEAR`), anything more frequent is
+    /// indistinguishable from continuous compounding at I80F48 precision.
+    pub fn effective_lending_apy(&self, compounds_per_year: u32) -> MarginfiResult<I80F48> {
+        check!(compounds_per_year > 0, MarginfiError::MathError);
+
+        let apr = self.effective_lending_apr()?;
+        let compounds_per_year = compounds_per_year.min(SECONDS_PER_YEAR.to_num::<u32>());
+
+        Ok(calc_apy_from_apr(apr, compounds_per_year).ok_or_else(math_error!())?)
+    }
+
+    pub fn configure(&mut self, config: &BankConfigOpt) -> MarginfiResult {
+        set_if_some!(self.config.asset_weight_init, config.asset_weight_init);
+        set_if_some!(self.config.asset_weight_maint, config.asset_weight_maint);
+        set_if_some!(
+            self.config.liability_weight_init,
+            config.liability_weight_init
+        );
+        set_if_some!(
+            self.config.liability_weight_maint,
+            config.liability_weight_maint
+        );
+        set_if_some!(self.config.deposit_limit, config.deposit_limit);
+        set_if_some!(self.config.borrow_limit, config.borrow_limit);
+
+        if let Some(operational_state) = config.operational_state {
+            // Leaving ReduceOnly with unsettled bad debt could trap depositor funds
+            if self.config.operational_state == BankOperationalState::ReduceOnly
+                && operational_state != BankOperationalState::ReduceOnly
+            {
+                check!(!self.should_auto_pause()?, MarginfiError::BankHasBadDebt);
+            }
+            self.config.operational_state = operational_state;
+        }
+
+        if let Some(ir_config) = &config.interest_rate_config {
+            self.config.interest_rate_config.update(ir_config);
+        }
+
+        set_if_some!(self.config.risk_tier, config.risk_tier);
+
+        if let Some(asset_tag) = config.asset_tag {
+            // Retagging a bank with deposits reclassifies collateral already in use
+            if asset_tag != self.config.asset_tag
+                && I80F48::from(self.total_asset_shares) != I80F48::ZERO
+            {
+                check!(
+                    config.force_asset_tag_change == Some(true),
+                    MarginfiError::InvalidConfig,
+                    "asset tag change on a bank with deposits requires force_asset_tag_change"
+                );
+            }
+            self.config.asset_tag = asset_tag;
+        }
+        set_if_some!(
+            self.config.total_asset_value_init_limit,
+            config.total_asset_value_init_limit
+        );
+        set_if_some!(self.config.oracle_max_age, config.oracle_max_age);
+        set_if_some!(self.config.deposit_fee, config.deposit_fee);
+        set_if_some!(self.config.withdrawal_fee, config.withdrawal_fee);
+
+        if let Some(flag) = config.permission_bad_debt_settlement {
+            msg!(
+                "setting bad debt settlement: {:?}",
+                config.permission_bad_debt_settlement
+            );
+            self.update_flag(flag, PERMISSIONLESS_BAD_DEBT_SETTLEMENT_FLAG);
+        }
+
+        if let Some(flag) = config.borrow_limit_within_deposit_limit {
+            msg!("setting borrow limit within deposit limit: {:?}", flag);
+            self.config
+                .update_config_flag(flag, BORROW_LIMIT_WITHIN_DEPOSIT_LIMIT);
+        }
+
+        if let Some(flag) = config.freeze_settings {
+            // A frozen bank can't be reconfigured out of trouble, so it must be solvent when frozen
+            if flag {
+                check!(!self.should_auto_pause()?, MarginfiError::BankHasBadDebt);
+            }
+            msg!(
+                "setting freeze settings: {:?}",
+                config.freeze_settings.unwrap()
+            );
+            self.update_flag(flag, FREEZE_SETTINGS);
+        }
+
+        self.config.validate()?;
+
+        Ok(())
+    }
+
+    // Configures just the borrow and deposit limits, ignoring all other values
+    pub fn configure_unfrozen_fields_only(&mut self, config: &BankConfigOpt) -> MarginfiResult {
+        set_if_some!(self.config.deposit_limit, config.deposit_limit);
+        set_if_some!(self.config.borrow_limit, config.borrow_limit);
+
+        Ok(())
+    }
+
+    /// `(asset_share_value, liability_share_value)` as they would be after `accrue_interest` at
+    /// `now`, without mutating the bank. If `now` is not after `last_update`, returns the current
+    /// share values.
+    ///
+    /// Note: unlike the rate helpers built on `current_interest_rates`, which ignore program fees,
+    /// this takes the `group` so program fees (which raise the borrowing rate) are included and the
+    /// projection matches `accrue_interest` exactly.
+    pub fn projected_share_values(
+        &self,
+        now: i64,
+        group: &MarginfiGroup,
+    ) -> MarginfiResult<(I80F48, I80F48)> {
+        let asset_share_value: I80F48 = self.asset_share_value.into();
+        let liability_share_value: I80F48 = self.liability_share_value.into();
+
+        let time_delta: u64 =
+            (now.saturating_sub(self.last_update).max(0) as u64).min(MAX_ACCRUAL_INTERVAL);
+        let total_assets = self.get_asset_amount(self.total_asset_shares.into())?;
+        let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
+        if time_delta == 0 || total_assets == I80F48::ZERO || total_liabilities == I80F48::ZERO {
+            return Ok((asset_share_value, liability_share_value));
+        }
+
+        let ir_calc = self.interest_rate_calculator(group)?;
+        let changes = calc_interest_rate_accrual_state_changes(
+            time_delta,
+            total_assets,
+            total_liabilities,
+            &ir_calc,
+            asset_share_value,
+            liability_share_value,
+        )
+        .ok_or_else(math_error!())?;
+
+        Ok((
+            changes.new_asset_share_value,
+            changes.new_liability_share_value,
+        ))
+    }
+
+    /// Calculate the interest rate accrual state changes for a given time period
+    ///
+    /// Collected protocol and insurance fees are stored in state.
+    /// A separate instruction is required to withdraw these fees.
+    ///
+    /// Accrues in steps of at most `MAX_ACCRUAL_INTERVAL` seconds (see `accrue_interest_capped`)
+    /// until `last_update` reaches `current_timestamp`.
+    pub fn accrue_interest(
+        &mut self,
+        current_timestamp: i64,
+        group: &MarginfiGroup,
+        #[cfg(not(feature = "client"))] bank: Pubkey,
+    ) -> MarginfiResult<()> {
+        loop {
+            self.accrue_interest_capped(
+                current_timestamp,
+                group,
+                #[cfg(not(feature = "client"))]
+                bank,
+            )?;
+
+            if self.last_update == current_timestamp {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply one discrete interest step without fees: `liability_share_value` grows by `(1 +
+    /// borrow_rate_fraction)` and the interest borrowers owe is spread over all deposits, so total
+    /// assets grow by exactly the same amount as total liabilities. Does nothing to deposits if the
+    /// bank has none.
+    pub fn apply_rate_step(&mut self, borrow_rate_fraction: I80F48) -> MarginfiResult<()> {
+        let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
+        let interest = total_liabilities
+            .checked_mul(borrow_rate_fraction)
+            .ok_or_else(math_error!())?;
+
+        self.liability_share_value = I80F48::from(self.liability_share_value)
+            .checked_mul(I80F48::ONE + borrow_rate_fraction)
+            .ok_or_else(math_error!())?
+            .into();
+
+        let total_asset_shares: I80F48 = self.total_asset_shares.into();
+        if total_asset_shares != I80F48::ZERO {
+            self.asset_share_value = interest
+                .checked_div(total_asset_shares)
+                .and_then(|delta| delta.checked_add(self.asset_share_value.into()))
+                .ok_or_else(math_error!())?
+                .into();
+        }
+
+        Ok(())
+    }
+
+    /// Accrue interest towards `current_timestamp`, applying at most `MAX_ACCRUAL_INTERVAL` seconds
+    /// of interest. `last_update` only advances by the seconds applied, so any remaining time is
+    /// accrued by later calls. Returns the elapsed seconds actually applied.
+    pub fn accrue_interest_capped(
+        &mut self,
+        current_timestamp: i64,
+        group: &MarginfiGroup,
+        #[cfg(not(feature = "client"))] bank: Pubkey,
+    ) -> MarginfiResult<i64> {
+        #[cfg(all(not(feature = "client"), feature = "debug"))]
+        anchor_lang::solana_program::log::sol_log_compute_units();
+
+        let time_delta: u64 = (current_timestamp - self.last_update).try_into().unwrap();
+        if time_delta == 0 {
+            return Ok(0);
+        }
+        let time_delta = time_delta.min(MAX_ACCRUAL_INTERVAL);
+
+        let total_assets = self.get_asset_amount(self.total_asset_shares.into())?;
+        let total_liabilities = self.get_liability_amount(self.total_liability_shares.into())?;
+
+        self.last_update += time_delta as i64;
+
+        if (total_assets == I80F48::ZERO) || (total_liabilities == I80F48::ZERO) {
+            #[cfg(not(feature = "client"))]
+            emit!(LendingPoolBankAccrueInterestEvent {
+                header: GroupEventHeader {
+                    marginfi_group: self.group,
+                    signer: None
+                },
+                bank,
+                mint: self.mint,
+                delta: time_delta,
+                fees_collected: 0.,
+                insurance_collected: 0.,
+            });
+
+            return Ok(time_delta as i64);
+        }
+        let ir_calc = self.interest_rate_calculator(group)?;
+
+        let InterestRateStateChanges {
+            new_asset_share_value: asset_share_value,
             new_liability_share_value: liability_share_value,
             insurance_fees_collected,
             group_fees_collected,
@@ -702,6 +1616,12 @@ impl Bank {
                     .ok_or_else(math_error!())?
                     .into()
             };
+            self.lifetime_group_fees = {
+                group_fees_collected
+                    .checked_add(self.lifetime_group_fees.into())
+                    .ok_or_else(math_error!())?
+                    .into()
+            };
         }
 
         if insurance_fees_collected > I80F48::ZERO {
@@ -711,6 +1631,12 @@ impl Bank {
                     .ok_or_else(math_error!())?
                     .into()
             };
+            self.lifetime_insurance_fees = {
+                insurance_fees_collected
+                    .checked_add(self.lifetime_insurance_fees.into())
+                    .ok_or_else(math_error!())?
+                    .into()
+            };
         }
         if protocol_fees_collected > I80F48::ZERO {
             self.collected_program_fees_outstanding = {
@@ -719,6 +1645,12 @@ impl Bank {
                     .ok_or_else(math_error!())?
                     .into()
             };
+            self.lifetime_program_fees = {
+                protocol_fees_collected
+                    .checked_add(self.lifetime_program_fees.into())
+                    .ok_or_else(math_error!())?
+                    .into()
+            };
         }
 
         #[cfg(not(feature = "client"))]
@@ -739,26 +1671,111 @@ impl Bank {
             });
         }
 
-        Ok(())
+        Ok(time_delta as i64)
     }
 
-    /// Updates bank cache with the actual values for interest/fee rates.
-    ///
-    /// Should be called in the end of each instruction calling `accrue_interest` to ensure the cache is up to date.
-    pub fn update_bank_cache(&mut self, group: &MarginfiGroup) -> MarginfiResult<()> {
-        let total_assets_amount = self.get_asset_amount(self.total_asset_shares.into())?;
-        let total_liabilities_amount =
-            self.get_liability_amount(self.total_liability_shares.into())?;
+    /// Total (insurance, group, program) fees ever collected by this bank, including fees that
+    /// have since been harvested.
+    pub fn lifetime_fees(&self) -> (I80F48, I80F48, I80F48) {
+        (
+            self.lifetime_insurance_fees.into(),
+            self.lifetime_group_fees.into(),
+            self.lifetime_program_fees.into(),
+        )
+    }
 
-        if (total_assets_amount == I80F48::ZERO) || (total_liabilities_amount == I80F48::ZERO) {
-            self.cache = BankCache::default();
-            return Ok(());
+    /// Where each type of fee collected by `collect_bank_fees` ends up, see `FeeRouting`.
+    pub fn fee_routing(&self, group: &MarginfiGroup) -> FeeRouting {
+        let group_fees = if self.fees_destination_account == Pubkey::default() {
+            self.fee_vault
+        } else {
+            self.fees_destination_account
+        };
+
+        FeeRouting {
+            insurance_fees: self.insurance_vault,
+            group_fees,
+            program_fees: group.fee_state_cache.global_fee_wallet,
         }
+    }
 
-        let ir_calc = self
-            .config
-            .interest_rate_config
-            .create_interest_rate_calculator(group);
+    /// Point-in-time copy of the bank's share values, totals, and outstanding fees, to compare
+    /// against later with e.g. `implied_accrued_rate`.
+    pub fn financial_snapshot(&self) -> BankFinancialSnapshot {
+        BankFinancialSnapshot {
+            asset_share_value: self.asset_share_value.into(),
+            liability_share_value: self.liability_share_value.into(),
+            total_asset_shares: self.total_asset_shares.into(),
+            total_liability_shares: self.total_liability_shares.into(),
+            collected_insurance_fees_outstanding: self.collected_insurance_fees_outstanding.into(),
+            collected_group_fees_outstanding: self.collected_group_fees_outstanding.into(),
+            collected_program_fees_outstanding: self.collected_program_fees_outstanding.into(),
+            last_update: self.last_update,
+        }
+    }
+
+    /// `(insurance, group, program)` outstanding fees accrued since `prior`, i.e. the increase of
+    /// each `collected_*_fees_outstanding` counter. A counter that fell, because the fees were
+    /// collected in between, reports zero: fees accrued before the collection are not recovered.
+    pub fn fee_delta(&self, prior: &BankFinancialSnapshot) -> (I80F48, I80F48, I80F48) {
+        let delta = |current: WrappedI80F48, prior: I80F48| {
+            I80F48::from(current)
+                .saturating_sub(prior)
+                .max(I80F48::ZERO)
+        };
+
+        (
+            delta(
+                self.collected_insurance_fees_outstanding,
+                prior.collected_insurance_fees_outstanding,
+            ),
+            delta(
+                self.collected_group_fees_outstanding,
+                prior.collected_group_fees_outstanding,
+            ),
+            delta(
+                self.collected_program_fees_outstanding,
+                prior.collected_program_fees_outstanding,
+            ),
+        )
+    }
+
+    /// Annualized (simple, not compounded) borrow rate implied by the growth of
+    /// `liability_share_value` since `prior` over `elapsed_seconds`, i.e. `(current / prior - 1) *
+    /// SECONDS_PER_YEAR / elapsed_seconds`. Should match the borrow APR of the configured curve if
+    /// accrual worked as expected.
+    pub fn implied_accrued_rate(
+        &self,
+        prior: &BankFinancialSnapshot,
+        elapsed_seconds: u64,
+    ) -> MarginfiResult<I80F48> {
+        check!(elapsed_seconds > 0, MarginfiError::MathError);
+
+        let growth = I80F48::from(self.liability_share_value)
+            .checked_div(prior.liability_share_value)
+            .and_then(|ratio| ratio.checked_sub(I80F48::ONE))
+            .ok_or_else(math_error!())?;
+
+        Ok(growth
+            .checked_mul(SECONDS_PER_YEAR)
+            .and_then(|annualized| annualized.checked_div(elapsed_seconds.into()))
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Updates bank cache with the actual values for interest/fee rates.
+    ///
+    /// Should be called in the end of each instruction calling `accrue_interest` to ensure the cache is up to date.
+    pub fn update_bank_cache(&mut self, group: &MarginfiGroup) -> MarginfiResult<()> {
+        let total_assets_amount = self.get_asset_amount(self.total_asset_shares.into())?;
+        let total_liabilities_amount =
+            self.get_liability_amount(self.total_liability_shares.into())?;
+
+        if (total_assets_amount == I80F48::ZERO) || (total_liabilities_amount == I80F48::ZERO) {
+            self.cache = BankCache::default();
+            return Ok(());
+        }
+
+        let ir_calc = self.interest_rate_calculator(group)?;
 
         let utilization_rate = total_liabilities_amount
             .checked_div(total_assets_amount)
@@ -873,24 +1890,95 @@ impl Bank {
         Ok(())
     }
 
+    /// Snap `total_asset_shares` and `total_liability_shares` to zero if they are below `epsilon`,
+    /// e.g. to clear rounding residue left after a full repay or withdraw.
+    ///
+    /// Note: `epsilon` must be tiny (on the order of `ZERO_AMOUNT_THRESHOLD`), anything larger
+    /// could erase real balances.
+    pub fn sweep_dust_shares(&mut self, epsilon: I80F48) {
+        if I80F48::from(self.total_asset_shares).is_zero_with_tolerance(epsilon) {
+            self.total_asset_shares = I80F48::ZERO.into();
+        }
+        if I80F48::from(self.total_liability_shares).is_zero_with_tolerance(epsilon) {
+            self.total_liability_shares = I80F48::ZERO.into();
+        }
+    }
+
     /// Socialize a loss `loss_amount` among depositors,
     /// the `total_deposit_shares` stays the same, but total value of deposits is
     /// reduced by `loss_amount`;
     pub fn socialize_loss(&mut self, loss_amount: I80F48) -> MarginfiResult {
+        self.asset_share_value = self.socialize_loss_impact(loss_amount)?.into();
+
+        Ok(())
+    }
+
+    /// The `asset_share_value` that `socialize_loss(loss_amount)` would leave behind, without
+    /// mutating the bank, so the dilution to depositors can be previewed.
+    pub fn socialize_loss_impact(&self, loss_amount: I80F48) -> MarginfiResult<I80F48> {
         let total_asset_shares: I80F48 = self.total_asset_shares.into();
         let old_asset_share_value: I80F48 = self.asset_share_value.into();
 
-        let new_share_value = total_asset_shares
+        Ok(total_asset_shares
             .checked_mul(old_asset_share_value)
             .ok_or_else(math_error!())?
             .checked_sub(loss_amount)
             .ok_or_else(math_error!())?
             .checked_div(total_asset_shares)
-            .ok_or_else(math_error!())?;
+            .ok_or_else(math_error!())?)
+    }
 
-        self.asset_share_value = new_share_value.into();
+    /// The `(fixed, rate)` program fees charged by this bank: the bank's own override if
+    /// `PROGRAM_FEE_OVERRIDE` is set, otherwise the group's `fee_state_cache`. Whether program fees
+    /// are charged at all is still controlled by the group.
+    pub fn effective_program_fee(&self, group: &MarginfiGroup) -> MarginfiResult<(I80F48, I80F48)> {
+        if self.config.config_flags & PROGRAM_FEE_OVERRIDE != 0 {
+            let ir_config = &self.config.interest_rate_config;
+            return Ok((
+                ir_config.program_fee_fixed_override.into(),
+                ir_config.program_fee_rate_override.into(),
+            ));
+        }
 
-        Ok(())
+        Ok((
+            group.fee_state_cache.program_fee_fixed.into(),
+            group.fee_state_cache.program_fee_rate.into(),
+        ))
+    }
+
+    /// True if this bank charges program fees, as controlled by the group's `GroupBankConfig`
+    pub fn program_fees_enabled(&self, group_bank_config: &GroupBankConfig) -> bool {
+        group_bank_config.program_fees
+    }
+
+    /// The bank's interest rate calculator, charging `effective_program_fee` if
+    /// `program_fees_enabled`
+    fn interest_rate_calculator(&self, group: &MarginfiGroup) -> MarginfiResult<InterestRateCalc> {
+        let mut ir_calc = self
+            .config
+            .interest_rate_config
+            .create_interest_rate_calculator(group);
+        ir_calc.add_program_fees = self.program_fees_enabled(&group.get_group_bank_config());
+        (ir_calc.program_fee_fixed, ir_calc.program_fee_rate) =
+            self.effective_program_fee(group)?;
+
+        Ok(ir_calc)
+    }
+
+    /// The bank's operational state after applying the group-level pause: `Paused` whenever the
+    /// group is paused, otherwise the bank's own state.
+    pub fn effective_operational_state(&self, group: &MarginfiGroup) -> BankOperationalState {
+        if group.is_paused() {
+            BankOperationalState::Paused
+        } else {
+            self.config.operational_state
+        }
+    }
+
+    /// True if the bank belongs to an arena group, in which case it behaves as an isolated
+    /// single-collateral market regardless of `config.risk_tier`.
+    pub fn is_arena_bank(&self, group: &MarginfiGroup) -> bool {
+        group.is_arena_group()
     }
 
     // Risk Control
@@ -916,12 +2004,81 @@ impl Bank {
         }
     }
 
+    /// Transition a `ReduceOnly` bank back to `Operational`. Only allowed if the bank is solvent
+    /// (see `check_utilization_ratio`) and its settings are not frozen.
+    pub fn reopen(&mut self) -> MarginfiResult {
+        check!(
+            self.config.operational_state == BankOperationalState::ReduceOnly,
+            MarginfiError::InvalidConfig,
+            "only ReduceOnly banks can be reopened"
+        );
+        check!(
+            !self.get_flag(FREEZE_SETTINGS),
+            MarginfiError::InvalidConfig,
+            "bank settings are frozen"
+        );
+        self.check_utilization_ratio()?;
+
+        self.config.operational_state = BankOperationalState::Operational;
+
+        Ok(())
+    }
+
+    /// `emissions_remaining` scaled by `10^emissions_decimals` (the `emissions_mint` decimals) and
+    /// floored to a `u64`, as needed when transferring emission tokens. Errors on overflow.
+    pub fn emissions_remaining_native(&self, emissions_decimals: u8) -> MarginfiResult<u64> {
+        let scaling_factor = *EXP_10_I80F48
+            .get(emissions_decimals as usize)
+            .ok_or_else(math_error!())?;
+
+        i80f48_to_u64_checked(
+            I80F48::from(self.emissions_remaining)
+                .checked_mul(scaling_factor)
+                .ok_or_else(math_error!())?,
+        )
+    }
+
+    /// A depositor's emissions over `elapsed_seconds`. `emissions_rate` is an APR (emitted tokens
+    /// per `1e(mint_decimals)` native deposit tokens per year), so this is
+    /// `emissions_rate * user_amount / 10^mint_decimals * elapsed_seconds / SECONDS_PER_YEAR`,
+    /// where `user_amount` is the deposit `user_shares` are worth. Each depositor earns in
+    /// proportion to their deposit, capped by `emissions_remaining`. Zero if lending emissions are
+    /// inactive.
+    ///
+    /// Note: this is not the pro-rata split `emissions_rate * elapsed_seconds * user_shares /
+    /// total_asset_shares` of a fixed per-second budget. With that reading, `emissions_rate` would
+    /// be tokens per second for the whole bank and a depositor's rate would shrink as others
+    /// deposit. Here it is a per-deposit APR, and each depositor's emissions do not depend on
+    /// `total_asset_shares`.
+    pub fn calc_user_emissions(
+        &self,
+        user_shares: I80F48,
+        elapsed_seconds: u64,
+    ) -> MarginfiResult<I80F48> {
+        if !self.get_flag(EMISSION_FLAG_LENDING_ACTIVE) || self.emissions_rate == 0 {
+            return Ok(I80F48::ZERO);
+        }
+
+        let user_amount = self.get_asset_amount(user_shares)?;
+        let emissions = I80F48::from_num(self.emissions_rate)
+            .checked_mul(user_amount)
+            .ok_or_else(math_error!())?
+            .checked_div(decimal_scale_factor(self.mint_decimals)?)
+            .ok_or_else(math_error!())?
+            .checked_mul(I80F48::from_num(elapsed_seconds))
+            .ok_or_else(math_error!())?
+            .checked_div(SECONDS_PER_YEAR)
+            .ok_or_else(math_error!())?;
+
+        Ok(emissions.min(self.emissions_remaining.into()))
+    }
+
     pub fn get_flag(&self, flag: u64) -> bool {
         (self.flags & flag) == flag
     }
 
     pub(crate) fn override_emissions_flag(&mut self, flag: u64) {
-        assert!(Self::verify_group_flags(flag));
+        assert!(Self::verify_emissions_flags(flag));
         self.flags = flag;
     }
 
@@ -944,6 +2101,235 @@ impl Bank {
     }
 }
 
+/// Destination of each fee type for a bank, see `Bank::fee_routing`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeRouting {
+    /// Insurance fees stay in the bank's `insurance_vault`
+    pub insurance_fees: Pubkey,
+    /// Group fees go to `fees_destination_account` (withdrawable permissionlessly) if one is set,
+    /// otherwise they stay in the bank's `fee_vault` until the admin withdraws them
+    pub group_fees: Pubkey,
+    /// Program fees go to the `global_fee_wallet`'s ATA for the bank's mint
+    pub program_fees: Pubkey,
+}
+
+/// A copy of a bank's accounting state at one point in time, see `Bank::financial_snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankFinancialSnapshot {
+    pub asset_share_value: I80F48,
+    pub liability_share_value: I80F48,
+    pub total_asset_shares: I80F48,
+    pub total_liability_shares: I80F48,
+    pub collected_insurance_fees_outstanding: I80F48,
+    pub collected_group_fees_outstanding: I80F48,
+    pub collected_program_fees_outstanding: I80F48,
+    /// The bank's `last_update` when the snapshot was taken
+    pub last_update: i64,
+}
+
+/// Qualitative bank status for frontends, see `Bank::status_badge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BankStatus {
+    Healthy,
+    HighUtilization,
+    NearCap,
+    ReduceOnly,
+    Paused,
+    Insolvent,
+}
+
+impl BankStatus {
+    /// Fraction of an active deposit or borrow limit at which a bank is shown as `NearCap`
+    pub const NEAR_CAP: I80F48 = I80F48::lit("0.95");
+}
+
+/// Shares minted for `amount` at `share_value`, i.e. `amount / share_value`. Independent of any
+/// live bank state, e.g. to recompute what a past deposit should have minted at a historical share
+/// value.
+pub fn shares_at_value(amount: I80F48, share_value: I80F48) -> MarginfiResult<I80F48> {
+    Ok(amount.checked_div(share_value).ok_or_else(math_error!())?)
+}
+
+/// Total value of all deposits across `banks`, where `prices[i]` is the price of `banks[i]`'s mint.
+pub fn group_tvl(banks: &[Bank], prices: &[I80F48]) -> MarginfiResult<I80F48> {
+    check_eq!(banks.len(), prices.len(), MarginfiError::InvalidConfig);
+
+    banks
+        .iter()
+        .zip(prices)
+        .try_fold(I80F48::ZERO, |tvl, (bank, price)| {
+            let total_assets = bank.get_asset_amount(bank.total_asset_shares.into())?;
+            let value = calc_value(total_assets, *price, bank.mint_decimals, None)?;
+
+            Ok(tvl.checked_add(value).ok_or_else(math_error!())?)
+        })
+}
+
+/// Sum of each bank's current insurance + group fee accrual, i.e. `fee APR * total liabilities`,
+/// in tokens per year. Program fees are set at the group level and are excluded.
+///
+/// Note: each bank contributes in its own native token units.
+pub fn group_fee_accrual_rate(banks: &[Bank]) -> MarginfiResult<I80F48> {
+    banks.iter().try_fold(I80F48::ZERO, |total, bank| {
+        let total_liabilities = bank.get_liability_amount(bank.total_liability_shares.into())?;
+        if total_liabilities == I80F48::ZERO {
+            return Ok(total);
+        }
+
+        let rates = bank.current_interest_rates()?;
+        let fee_apr = rates
+            .insurance_fee_apr
+            .checked_add(rates.group_fee_apr)
+            .ok_or_else(math_error!())?;
+
+        Ok(fee_apr
+            .checked_mul(total_liabilities)
+            .and_then(|fees| total.checked_add(fees))
+            .ok_or_else(math_error!())?)
+    })
+}
+
+/// Sum of every bank's `bad_debt`.
+///
+/// Note: each bank contributes in its own native token units.
+pub fn group_bad_debt(banks: &[Bank]) -> MarginfiResult<I80F48> {
+    banks.iter().try_fold(I80F48::ZERO, |total, bank| {
+        Ok(total
+            .checked_add(bank.bad_debt()?)
+            .ok_or_else(math_error!())?)
+    })
+}
+
+/// Insurance available across the group net of bad debt: the sum of `insurance_balances` (where
+/// `insurance_balances[i]` is the insurance vault balance of `banks[i]`) minus every bank's shortfall
+/// of assets against liabilities. Solvent banks add nothing beyond their insurance.
+///
+/// Note: each bank contributes in its own native token units.
+pub fn group_protocol_equity(
+    banks: &[Bank],
+    insurance_balances: &[I80F48],
+) -> MarginfiResult<I80F48> {
+    check_eq!(
+        banks.len(),
+        insurance_balances.len(),
+        MarginfiError::InvalidConfig
+    );
+
+    banks
+        .iter()
+        .zip(insurance_balances)
+        .try_fold(I80F48::ZERO, |equity, (bank, insurance)| {
+            let bad_debt = bank.bad_debt()?;
+
+            Ok(equity
+                .checked_add(*insurance)
+                .and_then(|equity| equity.checked_sub(bad_debt))
+                .ok_or_else(math_error!())?)
+        })
+}
+
+/// Average of each bank's `max_ltv` weighted by its total deposits. Zero if there are no deposits.
+///
+/// Note: deposits are weighted in each bank's own native token units.
+pub fn group_weighted_ltv(banks: &[Bank]) -> MarginfiResult<I80F48> {
+    let mut weighted_ltv_sum = I80F48::ZERO;
+    let mut total_assets = I80F48::ZERO;
+
+    for bank in banks {
+        let assets = bank.get_asset_amount(bank.total_asset_shares.into())?;
+        let ltv = max_ltv(
+            bank.config.asset_weight_init.into(),
+            bank.config.liability_weight_init.into(),
+        );
+
+        weighted_ltv_sum = ltv
+            .checked_mul(assets)
+            .and_then(|weighted| weighted_ltv_sum.checked_add(weighted))
+            .ok_or_else(math_error!())?;
+        total_assets = total_assets.checked_add(assets).ok_or_else(math_error!())?;
+    }
+
+    if total_assets == I80F48::ZERO {
+        return Ok(I80F48::ZERO);
+    }
+
+    Ok(weighted_ltv_sum
+        .checked_div(total_assets)
+        .ok_or_else(math_error!())?)
+}
+
+/// Banks whose `last_update` is older than their oracle max age (see
+/// `BankConfig::get_oracle_max_age`) as of `now`, i.e. the ones a keeper should refresh.
+pub fn stale_banks(banks: &[Bank], now: i64) -> Vec<&Bank> {
+    banks
+        .iter()
+        .filter(|bank| {
+            let max_age = bank.config.get_oracle_max_age().min(i64::MAX as u64) as i64;
+            bank.last_update.saturating_add(max_age) < now
+        })
+        .collect()
+}
+
+/// Validates a group and all of its banks together, e.g. before a group goes live. `group_key` is
+/// the group's address, which every bank must reference. Checks that:
+/// * each bank belongs to the group,
+/// * each bank's config and emode entries are valid, including the emode safe asset weight bound
+///   (see `EmodeEntry::max_safe_asset_weight`),
+/// * emode entries only reference tags used by some bank in `banks`,
+/// * `group.banks` matches the number of banks.
+///
+/// Every problem found is logged before failing with `InvalidConfig`.
+pub fn validate_group_config(
+    group_key: &Pubkey,
+    group: &MarginfiGroup,
+    banks: &[Bank],
+) -> MarginfiResult {
+    let known_tags: Vec<u16> = banks
+        .iter()
+        .map(|bank| bank.emode.emode_tag)
+        .filter(|tag| *tag != EMODE_TAG_EMPTY)
+        .collect();
+
+    let mut issues: Vec<String> = Vec::new();
+    for (i, bank) in banks.iter().enumerate() {
+        if bank.group != *group_key {
+            issues.push(format!("bank {} belongs to group {}", i, bank.group));
+        }
+        if bank.config.validate().is_err() {
+            issues.push(format!("bank {} has an invalid config", i));
+        }
+        if bank
+            .emode
+            .validate_entries_with_liability_weight(Some(bank.config.liability_weight_maint.into()))
+            .is_err()
+        {
+            issues.push(format!("bank {} has invalid emode entries", i));
+        }
+        if bank
+            .emode
+            .emode_config
+            .validate_against_tags(&known_tags)
+            .is_err()
+        {
+            issues.push(format!("bank {} has emode entries for unknown tags", i));
+        }
+    }
+    if group.banks as usize != banks.len() {
+        issues.push(format!(
+            "group expects {} banks, got {}",
+            group.banks,
+            banks.len()
+        ));
+    }
+
+    for issue in issues.iter() {
+        msg!("group config: {}", issue);
+    }
+    check!(issues.is_empty(), MarginfiError::InvalidConfig);
+
+    Ok(())
+}
+
 assert_struct_size!(BankConfig, 544);
 assert_struct_align!(BankConfig, 8);
 #[repr(C)]
@@ -986,7 +2372,11 @@ pub struct BankConfig {
     /// * 1 - Always set if bank created in 0.1.4 or later, or if migrated to the new pyth
     ///   oracle setup from a prior version. Not set in 0.1.3 or earlier banks using pyth that have
     ///   not yet migrated. Does nothing for banks that use switchboard.
-    /// * 2, 4, 8, 16, etc - reserved for future use.
+    /// * 2 - `BORROW_LIMIT_WITHIN_DEPOSIT_LIMIT`, if set, an active `borrow_limit` may not exceed
+    ///   an active `deposit_limit`.
+    /// * 4 - `PROGRAM_FEE_OVERRIDE`, if set, the program fee overrides in `interest_rate_config`
+    ///   are charged instead of the group's program fees.
+    /// * 8, 16, etc - reserved for future use.
     pub config_flags: u8,
 
     pub _pad1: [u8; 5],
@@ -1013,7 +2403,12 @@ pub struct BankConfig {
     /// * A %, as u32, e.g. 100% = u32::MAX, 50% = u32::MAX/2, etc.
     pub oracle_max_confidence: u32,
 
-    pub _padding1: [u8; 32],
+    /// Fraction of each deposit charged as a fee, from 0 to `MAX_DEPOSIT_FEE` (10%). Deducted
+    /// from the deposited amount before shares are minted.
+    pub deposit_fee: WrappedI80F48,
+    /// Fraction of each withdrawal charged as a fee, from 0 to `MAX_WITHDRAWAL_FEE` (10%). Accrues
+    /// to the `insurance_vault` to compensate the depositors left behind.
+    pub withdrawal_fee: WrappedI80F48,
 }
 
 // Used to provide a default initialization value
@@ -1039,12 +2434,20 @@ impl Default for BankConfig {
             oracle_max_age: 0,
             _padding0: [0; 2],
             oracle_max_confidence: 0,
-            _padding1: [0; 32],
+            deposit_fee: I80F48::ZERO.into(),
+            withdrawal_fee: I80F48::ZERO.into(),
         }
     }
 }
 
 impl BankConfig {
+    /// An all-zero config (every weight, limit, and fee is zero, `operational_state` is `Paused`).
+    /// Unlike `Default`, which fills in sensible weights and limits, this is an uninitialized
+    /// placeholder, e.g. for account-init code that wants true zeros. Never valid as-is.
+    pub fn zeroed_config() -> BankConfig {
+        <BankConfig as Zeroable>::zeroed()
+    }
+
     // Returns the asset and liability weights of a Bank under a specified risk type
     #[inline]
     pub fn get_weights(&self, req_type: RequirementType) -> (I80F48, I80F48) {
@@ -1099,12 +2502,57 @@ impl BankConfig {
         let liab_init_w = I80F48::from(self.liability_weight_init);
         let liab_maint_w = I80F48::from(self.liability_weight_maint);
 
-        check!(liab_init_w >= I80F48::ONE, MarginfiError::InvalidConfig);
+        // Liabilities are never valued below face value, or the protocol would under-collateralize
+        check!(
+            liab_init_w >= I80F48::ONE,
+            MarginfiError::InvalidConfig,
+            "liability_weight_init {} is below 1",
+            liab_init_w
+        );
+        check!(
+            liab_maint_w >= I80F48::ONE,
+            MarginfiError::InvalidConfig,
+            "liability_weight_maint {} is below 1",
+            liab_maint_w
+        );
+        check!(liab_maint_w <= liab_init_w, MarginfiError::InvalidConfig);
+
+        let deposit_fee = self.get_deposit_fee();
+        check!(
+            deposit_fee >= I80F48::ZERO && deposit_fee <= MAX_DEPOSIT_FEE,
+            MarginfiError::InvalidConfig
+        );
+        let withdrawal_fee = self.get_withdrawal_fee();
         check!(
-            liab_maint_w <= liab_init_w && liab_maint_w >= I80F48::ONE,
+            withdrawal_fee >= I80F48::ZERO && withdrawal_fee <= MAX_WITHDRAWAL_FEE,
             MarginfiError::InvalidConfig
         );
 
+        // Borrowing more than can ever be deposited is rarely intended, but some banks may want it,
+        // so this is opt-in. Inactive limits are exempt.
+        if self.config_flags & BORROW_LIMIT_WITHIN_DEPOSIT_LIMIT != 0
+            && self.is_deposit_limit_active()
+            && self.is_borrow_limit_active()
+        {
+            check!(
+                self.borrow_limit <= self.deposit_limit,
+                MarginfiError::InvalidConfig,
+                "borrow limit {} exceeds deposit limit {}",
+                self.borrow_limit,
+                self.deposit_limit
+            );
+        }
+
+        // An active limit of zero blocks every deposit, which is a misconfiguration on an
+        // operational bank. Deprecated (`ReduceOnly`) and `Paused` banks take no deposits anyway.
+        if self.operational_state == BankOperationalState::Operational {
+            check!(
+                self.deposit_limit != 0,
+                MarginfiError::InvalidConfig,
+                "deposit limit of zero is not allowed on an Operational bank"
+            );
+        }
+
         self.interest_rate_config.validate()?;
 
         if self.risk_tier == RiskTier::Isolated {
@@ -1137,6 +2585,15 @@ impl BankConfig {
         Ok(())
     }
 
+    /// True if a position with asset tag `tag` may be comingled with a position in this bank, e.g.
+    /// deposited as collateral against a borrow from this bank. See `asset_tag` for the rules:
+    /// * Matching tags are always compatible
+    /// * `ASSET_TAG_SOL` is compatible with every tag
+    /// * `ASSET_TAG_DEFAULT` and `ASSET_TAG_STAKED` are incompatible with each other
+    pub fn accepts_collateral_tag(&self, tag: u8) -> bool {
+        self.asset_tag == tag || self.asset_tag == ASSET_TAG_SOL || tag == ASSET_TAG_SOL
+    }
+
     pub fn usd_init_limit_active(&self) -> bool {
         self.total_asset_value_init_limit != TOTAL_ASSET_VALUE_INIT_LIMIT_INACTIVE
     }
@@ -1155,6 +2612,15 @@ impl BankConfig {
         (self.config_flags & PYTH_PUSH_MIGRATED) != 0
     }
 
+    /// Set (or with `None`, clear) the bank's `(fixed, rate)` program fee override. Program fees
+    /// belong to the protocol, so only the global fee admin should be allowed to set this.
+    pub fn set_program_fee_override(&mut self, program_fees: Option<(I80F48, I80F48)>) {
+        let (fixed, rate) = program_fees.unwrap_or((I80F48::ZERO, I80F48::ZERO));
+        self.interest_rate_config.program_fee_fixed_override = fixed.into();
+        self.interest_rate_config.program_fee_rate_override = rate.into();
+        self.update_config_flag(program_fees.is_some(), PROGRAM_FEE_OVERRIDE);
+    }
+
     pub fn update_config_flag(&mut self, value: bool, flag: u8) {
         if value {
             self.config_flags |= flag;
@@ -1175,6 +2641,23 @@ impl BankConfig {
         }
     }
 
+    #[inline]
+    pub fn get_deposit_fee(&self) -> I80F48 {
+        self.deposit_fee.into()
+    }
+
+    #[inline]
+    pub fn get_withdrawal_fee(&self) -> I80F48 {
+        self.withdrawal_fee.into()
+    }
+
+    /// The cap on the lending APR paid to depositors, or `None` if uncapped (zero)
+    #[inline]
+    pub fn get_max_lending_rate(&self) -> Option<I80F48> {
+        let max_lending_rate: I80F48 = self.interest_rate_config.max_lending_rate.into();
+        (max_lending_rate != I80F48::ZERO).then_some(max_lending_rate)
+    }
+
     #[inline]
     pub fn get_oracle_max_age(&self) -> u64 {
         match (self.oracle_max_age, self.oracle_setup) {
@@ -1182,6 +2665,153 @@ impl BankConfig {
             (n, _) => n as u64,
         }
     }
+
+    /// How often (in seconds) keepers should refresh this bank's price: half of
+    /// `get_oracle_max_age`, floored at 1, so a fresh price always lands before the old one
+    /// expires.
+    pub fn recommended_poll_interval(&self) -> u16 {
+        (self.get_oracle_max_age() / 2).clamp(1, u16::MAX as u64) as u16
+    }
+
+    /// Size of `risk_params_packed`: four 16-byte weights, two 8-byte limits, and the risk tier
+    pub const RISK_PARAMS_PACKED_LEN: usize = 4 * 16 + 2 * 8 + 1;
+
+    /// The risk parameters in a small byte array for events, instead of the whole config. Layout:
+    /// * 0..64: `asset_weight_init`, `asset_weight_maint`, `liability_weight_init`,
+    ///   `liability_weight_maint` (raw `I80F48` bytes)
+    /// * 64..80: `deposit_limit`, `borrow_limit` (little-endian)
+    /// * 80: `risk_tier`
+    pub fn risk_params_packed(&self) -> [u8; Self::RISK_PARAMS_PACKED_LEN] {
+        let mut packed = [0u8; Self::RISK_PARAMS_PACKED_LEN];
+        packed[0..16].copy_from_slice(&self.asset_weight_init.value);
+        packed[16..32].copy_from_slice(&self.asset_weight_maint.value);
+        packed[32..48].copy_from_slice(&self.liability_weight_init.value);
+        packed[48..64].copy_from_slice(&self.liability_weight_maint.value);
+        packed[64..72].copy_from_slice(&self.deposit_limit.to_le_bytes());
+        packed[72..80].copy_from_slice(&self.borrow_limit.to_le_bytes());
+        packed[80] = self.risk_tier as u8;
+
+        packed
+    }
+
+    /// Inverse of `risk_params_packed`: a default config with only the packed risk parameters
+    /// set. Errors with `InvalidConfig` on an unknown risk tier.
+    pub fn from_packed(packed: &[u8; Self::RISK_PARAMS_PACKED_LEN]) -> MarginfiResult<Self> {
+        let weight = |start: usize| WrappedI80F48 {
+            value: packed[start..start + 16].try_into().unwrap(),
+        };
+        let limit = |start: usize| u64::from_le_bytes(packed[start..start + 8].try_into().unwrap());
+        let risk_tier = match packed[80] {
+            0 => RiskTier::Collateral,
+            1 => RiskTier::Isolated,
+            tier => {
+                msg!("unknown risk tier {}", tier);
+                return err!(MarginfiError::InvalidConfig);
+            }
+        };
+
+        Ok(Self {
+            asset_weight_init: weight(0),
+            asset_weight_maint: weight(16),
+            liability_weight_init: weight(32),
+            liability_weight_maint: weight(48),
+            deposit_limit: limit(64),
+            borrow_limit: limit(72),
+            risk_tier,
+            ..Default::default()
+        })
+    }
+
+    /// True if the oracle setup or any oracle key differs from `previous`, i.e. prices cached under
+    /// the previous config must be discarded.
+    pub fn oracle_setup_changed(&self, previous: &BankConfig) -> bool {
+        self.oracle_setup != previous.oracle_setup || self.oracle_keys != previous.oracle_keys
+    }
+
+    /// Set `oracle_setup` and its `oracle_keys`, in the order `setup` expects them (see
+    /// `OracleSetup::required_key_count`). Unused slots are reset to default. Errors if the number
+    /// of keys is wrong, or if a key is default or repeated.
+    pub fn set_oracle_keys(&mut self, keys: &[Pubkey], setup: OracleSetup) -> MarginfiResult {
+        check!(
+            keys.len() == setup.required_key_count(),
+            MarginfiError::WrongNumberOfOracleAccounts,
+            "{:?} needs {} oracle keys, got {}",
+            setup,
+            setup.required_key_count(),
+            keys.len()
+        );
+        for (i, key) in keys.iter().enumerate() {
+            check!(
+                *key != Pubkey::default() && !keys[..i].contains(key),
+                MarginfiError::WrongOracleAccountKeys,
+                "oracle key {} is default or duplicated",
+                key
+            );
+        }
+
+        self.oracle_keys = [Pubkey::default(); MAX_ORACLE_KEYS];
+        self.oracle_keys[..keys.len()].copy_from_slice(keys);
+        self.oracle_setup = setup;
+
+        Ok(())
+    }
+
+    /// Soft risks in this config that `validate` accepts but reviewers should look at. Never
+    /// fails, an empty list means nothing stood out.
+    pub fn audit(&self) -> Vec<ConfigWarning> {
+        let mut warnings = Vec::new();
+
+        let ltv = max_ltv(
+            self.asset_weight_init.into(),
+            self.liability_weight_init.into(),
+        );
+        if ltv > ConfigWarning::HIGH_LTV {
+            warnings.push(ConfigWarning::HighLtv { ltv });
+        }
+
+        let max_interest_rate: I80F48 = self.interest_rate_config.max_interest_rate.into();
+        if max_interest_rate > ConfigWarning::HIGH_MAX_INTEREST_RATE {
+            warnings.push(ConfigWarning::HighMaxInterestRate { max_interest_rate });
+        }
+
+        if !self.usd_init_limit_active() && I80F48::from(self.asset_weight_init) > I80F48::ZERO {
+            warnings.push(ConfigWarning::InitLimitInactive);
+        }
+
+        warnings
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigWarningSeverity {
+    Low,
+    Medium,
+    High,
+}
+
+/// A soft risk flagged by `BankConfig::audit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigWarning {
+    /// Max loan-to-value (see `max_ltv`) above `HIGH_LTV`, little room before liquidation
+    HighLtv { ltv: I80F48 },
+    /// `max_interest_rate` above `HIGH_MAX_INTEREST_RATE`
+    HighMaxInterestRate { max_interest_rate: I80F48 },
+    /// The asset counts as collateral but has no `total_asset_value_init_limit`, so there is no
+    /// cap on the damage of a price manipulation
+    InitLimitInactive,
+}
+
+impl ConfigWarning {
+    pub const HIGH_LTV: I80F48 = I80F48::lit("0.9");
+    pub const HIGH_MAX_INTEREST_RATE: I80F48 = I80F48::lit("10");
+
+    pub fn severity(&self) -> ConfigWarningSeverity {
+        match self {
+            ConfigWarning::HighLtv { .. } => ConfigWarningSeverity::High,
+            ConfigWarning::HighMaxInterestRate { .. } => ConfigWarningSeverity::Medium,
+            ConfigWarning::InitLimitInactive => ConfigWarningSeverity::Low,
+        }
+    }
 }
 
 #[derive(AnchorDeserialize, AnchorSerialize, Default, Clone, PartialEq, Eq, TypeLayout)]
@@ -1210,32 +2840,164 @@ pub struct BankConfigOpt {
     pub permission_bad_debt_settlement: Option<bool>,
 
     pub freeze_settings: Option<bool>,
-}
 
-#[derive(Debug, Clone)]
-pub enum BankVaultType {
-    Liquidity,
-    Insurance,
-    Fee,
+    pub deposit_fee: Option<WrappedI80F48>,
+    pub withdrawal_fee: Option<WrappedI80F48>,
+
+    pub borrow_limit_within_deposit_limit: Option<bool>,
+
+    /// Must be `Some(true)` to change `asset_tag` on a bank that has deposits
+    pub force_asset_tag_change: Option<bool>,
 }
 
-impl BankVaultType {
-    pub fn get_seed(self) -> &'static [u8] {
-        match self {
-            BankVaultType::Liquidity => LIQUIDITY_VAULT_SEED.as_bytes(),
-            BankVaultType::Insurance => INSURANCE_VAULT_SEED.as_bytes(),
-            BankVaultType::Fee => FEE_VAULT_SEED.as_bytes(),
-        }
-    }
+impl BankConfigOpt {
+    /// True if applying this opt to `current` would increase risk, i.e. it raises an asset weight,
+    /// lowers a liability weight, raises (or deactivates) a deposit, borrow, or
+    /// `total_asset_value_init_limit`, or moves the bank from `RiskTier::Isolated` to
+    /// `RiskTier::Collateral`. Governance can route such changes through a timelock.
+    ///
+    /// Note: emode is configured separately and not covered here, see
+    /// `EmodeSettings::tightens_risk`.
+    pub fn is_risk_increasing(&self, current: &BankConfig) -> bool {
+        let raises = |new: Option<WrappedI80F48>, current: WrappedI80F48| {
+            new.is_some_and(|new| I80F48::from(new) > I80F48::from(current))
+        };
+        let lowers = |new: Option<WrappedI80F48>, current: WrappedI80F48| {
+            new.is_some_and(|new| I80F48::from(new) < I80F48::from(current))
+        };
+        // An inactive init limit is stored as 0 but means no limit at all
+        let init_limit = |limit: u64| {
+            if limit == TOTAL_ASSET_VALUE_INIT_LIMIT_INACTIVE {
+                u64::MAX
+            } else {
+                limit
+            }
+        };
 
-    pub fn get_authority_seed(self) -> &'static [u8] {
-        match self {
-            BankVaultType::Liquidity => LIQUIDITY_VAULT_AUTHORITY_SEED.as_bytes(),
-            BankVaultType::Insurance => INSURANCE_VAULT_AUTHORITY_SEED.as_bytes(),
-            BankVaultType::Fee => FEE_VAULT_AUTHORITY_SEED.as_bytes(),
-        }
+        raises(self.asset_weight_init, current.asset_weight_init)
+            || raises(self.asset_weight_maint, current.asset_weight_maint)
+            || lowers(self.liability_weight_init, current.liability_weight_init)
+            || lowers(self.liability_weight_maint, current.liability_weight_maint)
+            || self
+                .deposit_limit
+                .is_some_and(|limit| limit > current.deposit_limit)
+            || self
+                .borrow_limit
+                .is_some_and(|limit| limit > current.borrow_limit)
+            || self.total_asset_value_init_limit.is_some_and(|limit| {
+                init_limit(limit) > init_limit(current.total_asset_value_init_limit)
+            })
+            || (current.risk_tier == RiskTier::Isolated
+                && self.risk_tier == Some(RiskTier::Collateral))
     }
-}
+
+    /// `(field_name, new_value)` for every field that is `Some`, in declaration order, e.g. for
+    /// rendering a governance diff or hashing a change. Nested interest rate fields are prefixed
+    /// with `interest_rate_config.`. `WrappedI80F48` values render as decimals.
+    pub fn change_summary(&self) -> Vec<(String, String)> {
+        let mut changes = Vec::new();
+
+        macro_rules! push_if_some {
+            ($name:expr, $value:expr) => {
+                if let Some(value) = &$value {
+                    changes.push(($name.to_string(), format!("{:?}", value)));
+                }
+            };
+        }
+
+        push_if_some!("asset_weight_init", self.asset_weight_init);
+        push_if_some!("asset_weight_maint", self.asset_weight_maint);
+        push_if_some!("liability_weight_init", self.liability_weight_init);
+        push_if_some!("liability_weight_maint", self.liability_weight_maint);
+        push_if_some!("deposit_limit", self.deposit_limit);
+        push_if_some!("borrow_limit", self.borrow_limit);
+        push_if_some!("operational_state", self.operational_state);
+        if let Some(ir_config) = &self.interest_rate_config {
+            push_if_some!(
+                "interest_rate_config.optimal_utilization_rate",
+                ir_config.optimal_utilization_rate
+            );
+            push_if_some!(
+                "interest_rate_config.plateau_interest_rate",
+                ir_config.plateau_interest_rate
+            );
+            push_if_some!(
+                "interest_rate_config.max_interest_rate",
+                ir_config.max_interest_rate
+            );
+            push_if_some!(
+                "interest_rate_config.insurance_fee_fixed_apr",
+                ir_config.insurance_fee_fixed_apr
+            );
+            push_if_some!(
+                "interest_rate_config.insurance_ir_fee",
+                ir_config.insurance_ir_fee
+            );
+            push_if_some!(
+                "interest_rate_config.protocol_fixed_fee_apr",
+                ir_config.protocol_fixed_fee_apr
+            );
+            push_if_some!(
+                "interest_rate_config.protocol_ir_fee",
+                ir_config.protocol_ir_fee
+            );
+            push_if_some!(
+                "interest_rate_config.protocol_origination_fee",
+                ir_config.protocol_origination_fee
+            );
+            push_if_some!(
+                "interest_rate_config.max_lending_rate",
+                ir_config.max_lending_rate
+            );
+        }
+        push_if_some!("risk_tier", self.risk_tier);
+        push_if_some!("asset_tag", self.asset_tag);
+        push_if_some!(
+            "total_asset_value_init_limit",
+            self.total_asset_value_init_limit
+        );
+        push_if_some!("oracle_max_age", self.oracle_max_age);
+        push_if_some!(
+            "permission_bad_debt_settlement",
+            self.permission_bad_debt_settlement
+        );
+        push_if_some!("freeze_settings", self.freeze_settings);
+        push_if_some!("deposit_fee", self.deposit_fee);
+        push_if_some!("withdrawal_fee", self.withdrawal_fee);
+        push_if_some!(
+            "borrow_limit_within_deposit_limit",
+            self.borrow_limit_within_deposit_limit
+        );
+        push_if_some!("force_asset_tag_change", self.force_asset_tag_change);
+
+        changes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BankVaultType {
+    Liquidity,
+    Insurance,
+    Fee,
+}
+
+impl BankVaultType {
+    pub fn get_seed(self) -> &'static [u8] {
+        match self {
+            BankVaultType::Liquidity => LIQUIDITY_VAULT_SEED.as_bytes(),
+            BankVaultType::Insurance => INSURANCE_VAULT_SEED.as_bytes(),
+            BankVaultType::Fee => FEE_VAULT_SEED.as_bytes(),
+        }
+    }
+
+    pub fn get_authority_seed(self) -> &'static [u8] {
+        match self {
+            BankVaultType::Liquidity => LIQUIDITY_VAULT_AUTHORITY_SEED.as_bytes(),
+            BankVaultType::Insurance => INSURANCE_VAULT_AUTHORITY_SEED.as_bytes(),
+            BankVaultType::Fee => FEE_VAULT_AUTHORITY_SEED.as_bytes(),
+        }
+    }
+}
 
 #[macro_export]
 macro_rules! assert_eq_with_tolerance {
@@ -1282,8 +3044,14 @@ pub struct InterestRateConfig {
     // A one-time fee (not annualized) when a loan is initiated, similar to a startup fee
     pub protocol_origination_fee: WrappedI80F48,
 
-    pub _padding0: [u8; 16],
-    pub _padding1: [[u8; 32]; 3],
+    // Cap on the lending APR paid to depositors, zero means uncapped. Interest borrowers pay
+    // above what the cap lets depositors earn goes to the insurance fund instead.
+    pub max_lending_rate: WrappedI80F48,
+    // Program fees charged instead of the group's `fee_state_cache` fees, only if the bank's
+    // `PROGRAM_FEE_OVERRIDE` config flag is set
+    pub program_fee_fixed_override: WrappedI80F48,
+    pub program_fee_rate_override: WrappedI80F48,
+    pub _padding1: [[u8; 32]; 2],
 }
 
 impl InterestRateConfig {
@@ -1305,6 +3073,7 @@ impl InterestRateConfig {
             add_program_fees: group_bank_config.program_fees,
             program_fee_fixed: group.fee_state_cache.program_fee_fixed.into(),
             program_fee_rate: group.fee_state_cache.program_fee_rate.into(),
+            max_lending_rate: self.max_lending_rate.into(),
         }
     }
 
@@ -1320,10 +3089,136 @@ impl InterestRateConfig {
         check!(plateau_ir > I80F48::ZERO, MarginfiError::InvalidConfig);
         check!(max_ir > I80F48::ZERO, MarginfiError::InvalidConfig);
         check!(plateau_ir < max_ir, MarginfiError::InvalidConfig);
+        check!(
+            I80F48::from(self.max_lending_rate) >= I80F48::ZERO,
+            MarginfiError::InvalidConfig
+        );
+
+        // Rate fees are a share of the interest paid, taking more than all of it starves lenders
+        let ir_fees = I80F48::from(self.protocol_ir_fee)
+            .checked_add(self.insurance_ir_fee.into())
+            .ok_or_else(math_error!())?;
+        check!(
+            ir_fees <= I80F48::ONE,
+            MarginfiError::InvalidConfig,
+            "protocol_ir_fee + insurance_ir_fee = {} exceeds 1",
+            ir_fees
+        );
 
         Ok(())
     }
 
+    /// Returns a copy of this config with `plateau_interest_rate` and `max_interest_rate`
+    /// multiplied by `factor`, e.g. for stress testing. The scaled curve is validated, the
+    /// original is never modified.
+    pub fn scale_rates(&self, factor: I80F48) -> MarginfiResult<InterestRateConfig> {
+        let plateau_ir: I80F48 = self.plateau_interest_rate.into();
+        let max_ir: I80F48 = self.max_interest_rate.into();
+
+        let scaled = InterestRateConfig {
+            plateau_interest_rate: plateau_ir
+                .checked_mul(factor)
+                .ok_or_else(math_error!())?
+                .into(),
+            max_interest_rate: max_ir.checked_mul(factor).ok_or_else(math_error!())?.into(),
+            ..*self
+        };
+        scaled.validate()?;
+
+        Ok(scaled)
+    }
+
+    /// Smallest utilization in `[0, 1]` at which group + insurance fee revenue, per unit of
+    /// deposits, reaches `target_fee_rate` (APR). Fee revenue is `(group_fee_apr +
+    /// insurance_fee_apr) * utilization`, which never decreases with utilization, so the curve is
+    /// inverted by bisection. Program fees are not included. `None` if the target is unreachable
+    /// even at full utilization.
+    pub fn utilization_for_fee_revenue(
+        &self,
+        target_fee_rate: I80F48,
+    ) -> MarginfiResult<Option<I80F48>> {
+        let calc = self.create_interest_rate_calculator(&MarginfiGroup::default());
+        let fee_revenue_rate = |ur: I80F48| -> MarginfiResult<I80F48> {
+            let rates = calc.calc_interest_rate(ur).ok_or_else(math_error!())?;
+            Ok(rates
+                .group_fee_apr
+                .checked_add(rates.insurance_fee_apr)
+                .ok_or_else(math_error!())?
+                .checked_mul(ur)
+                .ok_or_else(math_error!())?)
+        };
+
+        if fee_revenue_rate(I80F48::ONE)? < target_fee_rate {
+            return Ok(None);
+        }
+        if fee_revenue_rate(I80F48::ZERO)? >= target_fee_rate {
+            return Ok(Some(I80F48::ZERO));
+        }
+
+        let mut low = I80F48::ZERO;
+        let mut high = I80F48::ONE;
+        // Each step halves the interval, 48 steps reach the precision of I80F48's fraction
+        for _ in 0..48 {
+            let mid = (low + high) / 2;
+            if fee_revenue_rate(mid)? >= target_fee_rate {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+
+        Ok(Some(high))
+    }
+
+    /// Borrow rate charged per second at `utilization`, i.e. the borrow APR (including insurance
+    /// and group fees, excluding program fees) divided by `SECONDS_PER_YEAR`.
+    pub fn per_second_rate(&self, utilization: I80F48) -> MarginfiResult<I80F48> {
+        Ok(self
+            .borrow_apr(utilization)?
+            .checked_div(SECONDS_PER_YEAR)
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Borrow rate charged per slot at `utilization`, i.e. the borrow APR (same as
+    /// `per_second_rate`) divided by `slots_per_year`.
+    pub fn per_slot_rate(
+        &self,
+        utilization: I80F48,
+        slots_per_year: u64,
+    ) -> MarginfiResult<I80F48> {
+        Ok(self
+            .borrow_apr(utilization)?
+            .checked_div(slots_per_year.into())
+            .ok_or_else(math_error!())?)
+    }
+
+    /// Borrow APR at `utilization`, including insurance and group fees, excluding program fees
+    fn borrow_apr(&self, utilization: I80F48) -> MarginfiResult<I80F48> {
+        let rates = self
+            .create_interest_rate_calculator(&MarginfiGroup::default())
+            .calc_interest_rate(utilization)
+            .ok_or_else(math_error!())?;
+
+        Ok(rates.borrowing_rate_apr)
+    }
+
+    /// Slope of the base interest rate curve (before fees) at `utilization`, i.e. how much the
+    /// base APR moves per unit of utilization. At the kink (`optimal_utilization_rate`) this is the
+    /// slope of the lower segment.
+    pub fn marginal_rate(&self, utilization: I80F48) -> MarginfiResult<I80F48> {
+        let optimal_ur: I80F48 = self.optimal_utilization_rate.into();
+        let plateau_ir: I80F48 = self.plateau_interest_rate.into();
+        let max_ir: I80F48 = self.max_interest_rate.into();
+
+        let slope = if utilization <= optimal_ur {
+            plateau_ir.checked_div(optimal_ur)
+        } else {
+            (max_ir - plateau_ir).checked_div(I80F48::ONE - optimal_ur)
+        };
+
+        Ok(slope.ok_or_else(math_error!())?)
+    }
+
     pub fn update(&mut self, ir_config: &InterestRateConfigOpt) {
         set_if_some!(
             self.optimal_utilization_rate,
@@ -1345,6 +3240,7 @@ impl InterestRateConfig {
             self.protocol_origination_fee,
             ir_config.protocol_origination_fee
         );
+        set_if_some!(self.max_lending_rate, ir_config.max_lending_rate);
     }
 }
 
@@ -1396,6 +3292,7 @@ pub struct InterestRateConfigOpt {
     pub protocol_fixed_fee_apr: Option<WrappedI80F48>,
     pub protocol_ir_fee: Option<WrappedI80F48>,
     pub protocol_origination_fee: Option<WrappedI80F48>,
+    pub max_lending_rate: Option<WrappedI80F48>,
 }
 
 #[repr(C)]
@@ -1476,7 +3373,8 @@ impl From<BankConfigCompact> for BankConfig {
             oracle_max_age: config.oracle_max_age,
             _padding0: [0; 2],
             oracle_max_confidence: config.oracle_max_confidence,
-            _padding1: [0; 32],
+            deposit_fee: I80F48::ZERO.into(),
+            withdrawal_fee: I80F48::ZERO.into(),
         }
     }
 }
@@ -1521,6 +3419,9 @@ pub struct InterestRateCalc {
     program_fee_rate: I80F48,
 
     add_program_fees: bool,
+
+    // Zero means uncapped
+    max_lending_rate: I80F48,
 }
 
 impl InterestRateCalc {
@@ -1544,7 +3445,7 @@ impl InterestRateCalc {
         let base_rate_apr = self.interest_rate_curve(utilization_ratio)?;
 
         // Lending rate is adjusted for utilization ratio to symmetrize payments between borrowers and depositors.
-        let lending_rate_apr = base_rate_apr.checked_mul(utilization_ratio)?;
+        let mut lending_rate_apr = base_rate_apr.checked_mul(utilization_ratio)?;
 
         // Borrowing rate is adjusted for fees.
         // borrowing_rate = base_rate + base_rate * rate_fee + total_fixed_fee_apr
@@ -1553,10 +3454,20 @@ impl InterestRateCalc {
             .checked_add(fee_fixed)?;
 
         let group_fee_apr = calc_fee_rate(base_rate_apr, group_fee_rate, group_fee_fixed)?;
-        let insurance_fee_apr =
+        let mut insurance_fee_apr =
             calc_fee_rate(base_rate_apr, insurance_fee_rate, insurance_fee_fixed)?;
         let protocol_fee_apr = calc_fee_rate(base_rate_apr, protocol_fee_rate, protocol_fee_fixed)?;
 
+        // Borrowers still pay the full rate, the part depositors can't earn above
+        // `max_lending_rate` goes to insurance. Lending rates apply to deposits and fee rates to
+        // liabilities, so the excess is scaled back up by the utilization ratio.
+        if self.max_lending_rate > I80F48::ZERO && lending_rate_apr > self.max_lending_rate {
+            let excess_lending_apr = lending_rate_apr - self.max_lending_rate;
+            lending_rate_apr = self.max_lending_rate;
+            insurance_fee_apr = insurance_fee_apr
+                .checked_add(excess_lending_apr.checked_div(utilization_ratio)?)?;
+        }
+
         assert!(lending_rate_apr >= I80F48::ZERO);
         assert!(borrowing_rate_apr >= I80F48::ZERO);
         assert!(group_fee_apr >= I80F48::ZERO);
@@ -1749,6 +3660,28 @@ fn calc_interest_payment_for_period(apr: I80F48, time_delta: u64, value: I80F48)
     Some(interest_payment)
 }
 
+/// Converts an APR into an APY compounded `compounds_per_year` times.
+/// Formula: APY = (1 + APR / n)^n - 1
+fn calc_apy_from_apr(apr: I80F48, compounds_per_year: u32) -> Option<I80F48> {
+    let rate_per_period = apr.checked_div(I80F48::from_num(compounds_per_year))?;
+    let mut base = I80F48::ONE.checked_add(rate_per_period)?;
+    let mut exp = compounds_per_year;
+    let mut growth = I80F48::ONE;
+
+    // Exponentiation by squaring, so large `n` takes log2(n) steps
+    while exp > 0 {
+        if exp & 1 == 1 {
+            growth = growth.checked_mul(base)?;
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = base.checked_mul(base)?;
+        }
+    }
+
+    growth.checked_sub(I80F48::ONE)
+}
+
 #[derive(Debug, Clone)]
 pub struct Fees {
     pub insurance_fee_rate: I80F48,
@@ -1786,8 +3719,10 @@ impl From<InterestRateConfigCompact> for InterestRateConfig {
             protocol_fixed_fee_apr: ir_config.protocol_fixed_fee_apr,
             protocol_ir_fee: ir_config.protocol_ir_fee,
             protocol_origination_fee: ir_config.protocol_origination_fee,
-            _padding0: [0; 16],
-            _padding1: [[0; 32]; 3],
+            max_lending_rate: I80F48::ZERO.into(),
+            program_fee_fixed_override: I80F48::ZERO.into(),
+            program_fee_rate_override: I80F48::ZERO.into(),
+            _padding1: [[0; 32]; 2],
         }
     }
 }
@@ -1811,7 +3746,11 @@ impl From<InterestRateConfig> for InterestRateConfigCompact {
 mod tests {
     use std::time::{SystemTime, UNIX_EPOCH};
 
-    use crate::constants::{PROTOCOL_FEE_FIXED_DEFAULT, PROTOCOL_FEE_RATE_DEFAULT};
+    use crate::constants::{
+        ASSET_TAG_STAKED, EMISSION_FLAG_BORROW_ACTIVE, PROTOCOL_FEE_FIXED_DEFAULT,
+        PROTOCOL_FEE_RATE_DEFAULT,
+    };
+    use crate::state::emode::{EmodeConfig, EmodeEntry};
 
     use super::*;
     use fixed_macro::types::I80F48;
@@ -1827,7 +3766,7 @@ mod tests {
             calc_interest_payment_for_period(apr, time_delta, value).unwrap(),
             I80F48::ONE,
             I80F48!(0.001)
-        )
+        );
     }
 
     /// Tests that the interest payment for a 1 year period with 50% APR is 0.5.
@@ -2148,4 +4087,2428 @@ mod tests {
 
         Ok(())
     }
+
+    /// Group with program fees enabled at the typical rates.
+    fn program_fee_group() -> MarginfiGroup {
+        MarginfiGroup {
+            group_flags: PROGRAM_FEES_ENABLED,
+            fee_state_cache: FeeStateCache {
+                program_fee_fixed: PROTOCOL_FEE_FIXED_DEFAULT.into(),
+                program_fee_rate: PROTOCOL_FEE_RATE_DEFAULT.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Bank with share values of 1 and the given totals, on a curve with a 50% optimal
+    /// utilization, 40% plateau rate, and 300% max rate.
+    fn bank_with_totals(total_assets: I80F48, total_liabilities: I80F48) -> Bank {
+        Bank {
+            asset_share_value: I80F48::ONE.into(),
+            liability_share_value: I80F48::ONE.into(),
+            total_asset_shares: total_assets.into(),
+            total_liability_shares: total_liabilities.into(),
+            config: BankConfig {
+                borrow_limit: u64::MAX,
+                deposit_limit: u64::MAX,
+                interest_rate_config: InterestRateConfig {
+                    optimal_utilization_rate: I80F48!(0.5).into(),
+                    plateau_interest_rate: I80F48!(0.4).into(),
+                    max_interest_rate: I80F48!(3).into(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    /// ur: 0.5
+    /// lending apr: 0.4 * 0.5 = 0.2
+    fn lending_apy_compounds_apr() {
+        let bank = bank_with_totals(I80F48!(100), I80F48!(50));
+
+        let apr = bank.effective_lending_apr().unwrap();
+        assert_eq_with_tolerance!(apr, I80F48!(0.2), I80F48!(0.000001));
+
+        // Compounding once a year is just the APR
+        assert_eq_with_tolerance!(
+            bank.effective_lending_apy(1).unwrap(),
+            apr,
+            I80F48!(0.000001)
+        );
+        // (1 + 0.2 / 12)^12 - 1
+        assert_eq_with_tolerance!(
+            bank.effective_lending_apy(12).unwrap(),
+            I80F48!(0.219391),
+            I80F48!(0.000001)
+        );
+        // (1 + 0.2 / 365)^365 - 1
+        assert_eq_with_tolerance!(
+            bank.effective_lending_apy(365).unwrap(),
+            I80F48!(0.221336),
+            I80F48!(0.000001)
+        );
+        // Approaches continuous compounding: e^0.2 - 1
+        assert_eq_with_tolerance!(
+            bank.effective_lending_apy(u32::MAX).unwrap(),
+            I80F48!(0.221403),
+            I80F48!(0.00001)
+        );
+
+        assert!(bank.effective_lending_apy(0).is_err());
+    }
+
+    #[test]
+    fn lending_apy_empty_bank_is_zero() {
+        let bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+
+        assert_eq!(bank.effective_lending_apr().unwrap(), I80F48::ZERO);
+        assert_eq!(bank.effective_lending_apy(365).unwrap(), I80F48::ZERO);
+    }
+
+    #[test]
+    fn lifetime_fees_survive_harvest() {
+        let mut bank = bank_with_totals(I80F48!(1_000_000), I80F48!(500_000));
+        bank.config.interest_rate_config.insurance_ir_fee = I80F48!(0.1).into();
+        bank.config.interest_rate_config.protocol_fixed_fee_apr = I80F48!(0.01).into();
+
+        let group = program_fee_group();
+
+        let mut expected = (I80F48::ZERO, I80F48::ZERO, I80F48::ZERO);
+        for i in 1..=3 {
+            bank.accrue_interest(
+                i * 3600,
+                &group,
+                #[cfg(not(feature = "client"))]
+                Pubkey::default(),
+            )
+            .unwrap();
+
+            expected.0 += I80F48::from(bank.collected_insurance_fees_outstanding);
+            expected.1 += I80F48::from(bank.collected_group_fees_outstanding);
+            expected.2 += I80F48::from(bank.collected_program_fees_outstanding);
+
+            // Harvest
+            bank.collected_insurance_fees_outstanding = I80F48::ZERO.into();
+            bank.collected_group_fees_outstanding = I80F48::ZERO.into();
+            bank.collected_program_fees_outstanding = I80F48::ZERO.into();
+        }
+
+        let (insurance, group_fees, program) = bank.lifetime_fees();
+        assert!(insurance > I80F48::ZERO);
+        assert!(group_fees > I80F48::ZERO);
+        assert!(program > I80F48::ZERO);
+        assert_eq!((insurance, group_fees, program), expected);
+    }
+
+    #[test]
+    fn available_liquidity_partially_utilized() {
+        let bank = bank_with_totals(I80F48!(1_000), I80F48!(400));
+        assert_eq!(bank.available_liquidity().unwrap(), I80F48!(600));
+
+        let bank = bank_with_totals(I80F48!(1_000), I80F48::ZERO);
+        assert_eq!(bank.available_liquidity().unwrap(), I80F48!(1_000));
+    }
+
+    #[test]
+    fn available_liquidity_fully_utilized() {
+        let bank = bank_with_totals(I80F48!(1_000), I80F48!(1_000));
+        assert_eq!(bank.available_liquidity().unwrap(), I80F48::ZERO);
+
+        // Liabilities exceeding assets (bad debt) never report negative liquidity
+        let bank = bank_with_totals(I80F48!(1_000), I80F48!(1_200));
+        assert_eq!(bank.available_liquidity().unwrap(), I80F48::ZERO);
+    }
+
+    #[test]
+    fn can_withdraw_up_to_available_liquidity() {
+        let bank = bank_with_totals(I80F48!(1_000), I80F48!(400));
+
+        assert!(bank.can_withdraw(I80F48!(600)).unwrap());
+        assert!(!bank.can_withdraw(I80F48!(600.000001)).unwrap());
+    }
+
+    #[test]
+    fn scale_rates_doubles_curve() {
+        let config = bank_with_totals(I80F48::ZERO, I80F48::ZERO)
+            .config
+            .interest_rate_config;
+
+        let scaled = config.scale_rates(I80F48!(2)).unwrap();
+        assert!(scaled.validate().is_ok());
+        assert_eq_with_tolerance!(
+            I80F48::from(scaled.plateau_interest_rate),
+            I80F48!(0.8),
+            I80F48!(0.000001)
+        );
+        assert_eq!(I80F48::from(scaled.max_interest_rate), I80F48!(6));
+        assert_eq!(
+            scaled.optimal_utilization_rate,
+            config.optimal_utilization_rate
+        );
+        // Original untouched
+        assert_eq!(I80F48::from(config.plateau_interest_rate), I80F48!(0.4));
+
+        // Zeroing the curve breaks its invariants
+        assert!(config.scale_rates(I80F48::ZERO).is_err());
+    }
+
+    #[test]
+    fn accepts_collateral_tag_combinations() {
+        let config_with_tag = |asset_tag| BankConfig {
+            asset_tag,
+            ..Default::default()
+        };
+        let default = config_with_tag(ASSET_TAG_DEFAULT);
+        let sol = config_with_tag(ASSET_TAG_SOL);
+        let staked = config_with_tag(ASSET_TAG_STAKED);
+
+        assert!(default.accepts_collateral_tag(ASSET_TAG_DEFAULT));
+        assert!(default.accepts_collateral_tag(ASSET_TAG_SOL));
+        assert!(!default.accepts_collateral_tag(ASSET_TAG_STAKED));
+
+        assert!(sol.accepts_collateral_tag(ASSET_TAG_DEFAULT));
+        assert!(sol.accepts_collateral_tag(ASSET_TAG_SOL));
+        assert!(sol.accepts_collateral_tag(ASSET_TAG_STAKED));
+
+        assert!(!staked.accepts_collateral_tag(ASSET_TAG_DEFAULT));
+        assert!(staked.accepts_collateral_tag(ASSET_TAG_SOL));
+        assert!(staked.accepts_collateral_tag(ASSET_TAG_STAKED));
+    }
+
+    #[test]
+    fn group_tvl_sums_bank_values() {
+        // 1,000 tokens with 6 decimals at $1
+        let mut usdc = bank_with_totals(I80F48!(1_000_000_000), I80F48::ZERO);
+        usdc.mint_decimals = 6;
+        // 10 tokens with 9 decimals at $150
+        let mut sol = bank_with_totals(I80F48!(10_000_000_000), I80F48::ZERO);
+        sol.mint_decimals = 9;
+
+        let banks = [usdc, sol];
+        assert_eq!(
+            group_tvl(&banks, &[I80F48::ONE, I80F48!(150)]).unwrap(),
+            I80F48!(2_500)
+        );
+        assert_eq!(group_tvl(&[], &[]).unwrap(), I80F48::ZERO);
+        assert!(group_tvl(&banks, &[I80F48::ONE]).is_err());
+    }
+
+    #[test]
+    fn should_auto_pause_insolvent_bank() {
+        let insolvent = bank_with_totals(I80F48!(1_000), I80F48!(1_001));
+        assert!(insolvent.should_auto_pause().unwrap());
+        assert!(insolvent.check_utilization_ratio().is_err());
+
+        let solvent = bank_with_totals(I80F48!(1_000), I80F48!(1_000));
+        assert!(!solvent.should_auto_pause().unwrap());
+        assert!(solvent.check_utilization_ratio().is_ok());
+    }
+
+    #[test]
+    fn display_asset_weight_init_applies_discount() {
+        // 1,000 tokens with 6 decimals at $2 = $2,000 of deposits
+        let mut bank = bank_with_totals(I80F48!(1_000_000_000), I80F48::ZERO);
+        bank.mint_decimals = 6;
+        bank.config.asset_weight_init = I80F48!(0.8).into();
+        let price = I80F48!(2);
+
+        // Limit inactive: the raw weight
+        assert!((bank.display_asset_weight_init(price).unwrap() - 0.8).abs() < 1e-9);
+
+        // $500 limit: discounted by 500 / 2000
+        bank.config.total_asset_value_init_limit = 500;
+        let discount = bank
+            .maybe_get_asset_weight_init_discount(price)
+            .unwrap()
+            .unwrap();
+        let expected = I80F48::from(bank.config.asset_weight_init) * discount;
+        let displayed = bank.display_asset_weight_init(price).unwrap();
+
+        assert!((displayed - expected.to_num::<f64>()).abs() < 1e-12);
+        assert!((displayed - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn group_fee_accrual_rate_across_utilizations() {
+        let with_fees = |total_assets, total_liabilities| {
+            let mut bank = bank_with_totals(total_assets, total_liabilities);
+            bank.config.interest_rate_config.insurance_ir_fee = I80F48!(0.1).into();
+            bank.config.interest_rate_config.protocol_fixed_fee_apr = I80F48!(0.01).into();
+            bank
+        };
+
+        // ur 0.5: base 0.4, fees 0.4 * 0.1 + 0.01 = 0.05 on 500 liabilities = 25
+        let half = with_fees(I80F48!(1_000), I80F48!(500));
+        // ur 0.25: base 0.2, fees 0.2 * 0.1 + 0.01 = 0.03 on 250 liabilities = 7.5
+        let quarter = with_fees(I80F48!(1_000), I80F48!(250));
+        // No liabilities, no fees
+        let idle = with_fees(I80F48!(1_000), I80F48::ZERO);
+
+        assert_eq_with_tolerance!(
+            group_fee_accrual_rate(&[half, quarter, idle]).unwrap(),
+            I80F48!(32.5),
+            I80F48!(0.000001)
+        );
+        assert_eq!(group_fee_accrual_rate(&[idle]).unwrap(), I80F48::ZERO);
+        assert_eq!(group_fee_accrual_rate(&[]).unwrap(), I80F48::ZERO);
+    }
+
+    #[test]
+    fn deposit_fee_zero_by_default() {
+        let bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+
+        assert_eq!(bank.config.get_deposit_fee(), I80F48::ZERO);
+        assert_eq!(bank.calc_deposit_fee(I80F48!(1_000)).unwrap(), I80F48::ZERO);
+    }
+
+    #[test]
+    fn deposit_fee_capped() {
+        let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        bank.config.risk_tier = RiskTier::Collateral;
+
+        bank.config.deposit_fee = MAX_DEPOSIT_FEE.into();
+        assert!(bank.config.validate().is_ok());
+        assert_eq_with_tolerance!(
+            bank.calc_deposit_fee(I80F48!(1_000)).unwrap(),
+            I80F48!(100),
+            I80F48!(0.000001)
+        );
+
+        bank.config.deposit_fee = I80F48!(0.11).into();
+        assert!(bank.config.validate().is_err());
+
+        bank.config.deposit_fee = I80F48!(-0.01).into();
+        assert!(bank.config.validate().is_err());
+    }
+
+    #[test]
+    fn withdrawal_fee_deducted_and_counted() {
+        let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        bank.config.risk_tier = RiskTier::Collateral;
+        bank.collected_insurance_fees_outstanding = I80F48!(5).into();
+
+        // No fee by default
+        assert_eq!(
+            bank.charge_withdrawal_fee(I80F48!(1_000)).unwrap(),
+            I80F48::ZERO
+        );
+        assert_eq!(
+            I80F48::from(bank.collected_insurance_fees_outstanding),
+            I80F48!(5)
+        );
+
+        bank.config.withdrawal_fee = I80F48!(0.01).into();
+        assert!(bank.config.validate().is_ok());
+
+        let amount = I80F48!(1_000);
+        let fee = bank.charge_withdrawal_fee(amount).unwrap();
+        assert_eq_with_tolerance!(fee, I80F48!(10), I80F48!(0.000001));
+        assert_eq_with_tolerance!(amount - fee, I80F48!(990), I80F48!(0.000001));
+        assert_eq!(
+            I80F48::from(bank.collected_insurance_fees_outstanding),
+            I80F48!(5) + fee
+        );
+        assert_eq!(I80F48::from(bank.lifetime_insurance_fees), fee);
+
+        bank.config.withdrawal_fee = I80F48!(0.11).into();
+        assert!(bank.config.validate().is_err());
+    }
+
+    #[test]
+    fn borrow_limit_within_deposit_limit() {
+        let mut config = bank_with_totals(I80F48::ZERO, I80F48::ZERO).config;
+        config.deposit_limit = 1_000;
+        config.borrow_limit = 2_000;
+
+        // Opt-in only
+        assert!(config.validate().is_ok());
+
+        config.update_config_flag(true, BORROW_LIMIT_WITHIN_DEPOSIT_LIMIT);
+        assert!(config.validate().is_err());
+
+        config.borrow_limit = 1_000;
+        assert!(config.validate().is_ok());
+
+        // Inactive limits are exempt
+        config.borrow_limit = 2_000;
+        config.deposit_limit = u64::MAX;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn liability_weights_never_below_one() {
+        let mut config = bank_with_totals(I80F48::ZERO, I80F48::ZERO).config;
+        config.liability_weight_init = I80F48!(1.2).into();
+        config.liability_weight_maint = I80F48!(1.1).into();
+        assert!(config.validate().is_ok());
+
+        // Face value is the floor
+        config.liability_weight_init = I80F48::ONE.into();
+        config.liability_weight_maint = I80F48::ONE.into();
+        assert!(config.validate().is_ok());
+
+        config.liability_weight_maint = I80F48!(0.99).into();
+        assert_eq!(config.validate(), Err(MarginfiError::InvalidConfig.into()));
+
+        config.liability_weight_init = I80F48!(0.99).into();
+        assert_eq!(config.validate(), Err(MarginfiError::InvalidConfig.into()));
+    }
+
+    #[test]
+    fn zero_deposit_limit_only_on_deprecated_bank() {
+        let mut config = bank_with_totals(I80F48::ZERO, I80F48::ZERO).config;
+        config.operational_state = BankOperationalState::Operational;
+        config.deposit_limit = 0;
+        assert!(config.validate().is_err());
+
+        config.deposit_limit = 1;
+        assert!(config.validate().is_ok());
+
+        config.deposit_limit = 0;
+        config.operational_state = BankOperationalState::ReduceOnly;
+        assert!(config.validate().is_ok());
+
+        config.operational_state = BankOperationalState::Paused;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn group_pause_overrides_bank_operational_state() {
+        let mut group = program_fee_group();
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(500));
+
+        for state in [
+            BankOperationalState::Operational,
+            BankOperationalState::ReduceOnly,
+            BankOperationalState::Paused,
+        ] {
+            bank.config.operational_state = state;
+            assert_eq!(bank.effective_operational_state(&group), state);
+        }
+
+        group.set_paused(true);
+        assert!(group.is_paused());
+        // Pausing the group leaves other flags (and thus program fees) intact
+        assert!(group.program_fees_enabled());
+        assert!(group.get_group_bank_config().program_fees);
+        for state in [
+            BankOperationalState::Operational,
+            BankOperationalState::ReduceOnly,
+            BankOperationalState::Paused,
+        ] {
+            bank.config.operational_state = state;
+            assert_eq!(
+                bank.effective_operational_state(&group),
+                BankOperationalState::Paused
+            );
+        }
+
+        group.set_paused(false);
+        assert!(!group.is_paused());
+        bank.config.operational_state = BankOperationalState::Operational;
+        assert_eq!(
+            bank.effective_operational_state(&group),
+            BankOperationalState::Operational
+        );
+    }
+
+    #[test]
+    fn min_viable_deposit_boundary() {
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48::ZERO);
+        assert_eq!(bank.min_viable_deposit().unwrap(), I80F48::ONE);
+
+        // Share value inflated to 2^50: one share DELTA (2^-48) is worth 4 native units
+        bank.asset_share_value = I80F48::from_num(1u64 << 50).into();
+        let min = bank.min_viable_deposit().unwrap();
+        assert_eq!(min, I80F48!(4));
+        assert!(bank.get_asset_shares(min).unwrap() > I80F48::ZERO);
+        assert_eq!(
+            bank.get_asset_shares(min - I80F48::ONE).unwrap(),
+            I80F48::ZERO
+        );
+    }
+
+    #[test]
+    fn reopen_solvent_reduce_only_bank() {
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(900));
+        bank.config.operational_state = BankOperationalState::ReduceOnly;
+
+        bank.reopen().unwrap();
+        assert_eq!(
+            bank.config.operational_state,
+            BankOperationalState::Operational
+        );
+
+        // Already operational: nothing to reopen
+        assert!(bank.reopen().is_err());
+    }
+
+    #[test]
+    fn reopen_rejects_insolvent_or_frozen_bank() {
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(1_100));
+        bank.config.operational_state = BankOperationalState::ReduceOnly;
+        assert!(bank.reopen().is_err());
+        assert_eq!(
+            bank.config.operational_state,
+            BankOperationalState::ReduceOnly
+        );
+
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(500));
+        bank.config.operational_state = BankOperationalState::ReduceOnly;
+        bank.update_flag(true, FREEZE_SETTINGS);
+        assert!(bank.reopen().is_err());
+        assert_eq!(
+            bank.config.operational_state,
+            BankOperationalState::ReduceOnly
+        );
+
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(500));
+        bank.config.operational_state = BankOperationalState::Paused;
+        assert!(bank.reopen().is_err());
+    }
+
+    #[test]
+    fn utilization_for_fee_revenue_inverts_fee_curve() {
+        let mut config = bank_with_totals(I80F48::ZERO, I80F48::ZERO)
+            .config
+            .interest_rate_config;
+        config.insurance_ir_fee = I80F48!(0.1).into();
+        config.protocol_ir_fee = I80F48!(0.1).into();
+
+        // At optimal utilization (0.5) the base rate is 0.4, fees are 20% of that, so fee revenue
+        // per unit deposited is 0.4 * 0.2 * 0.5 = 0.04
+        let ur = config
+            .utilization_for_fee_revenue(I80F48!(0.04))
+            .unwrap()
+            .unwrap();
+        assert_eq_with_tolerance!(ur, I80F48!(0.5), I80F48!(0.000001));
+
+        // Zero target is met with no utilization at all
+        assert_eq!(
+            config.utilization_for_fee_revenue(I80F48::ZERO).unwrap(),
+            Some(I80F48::ZERO)
+        );
+    }
+
+    #[test]
+    fn utilization_for_fee_revenue_unreachable_target() {
+        let mut config = bank_with_totals(I80F48::ZERO, I80F48::ZERO)
+            .config
+            .interest_rate_config;
+        config.insurance_ir_fee = I80F48!(0.1).into();
+        config.protocol_ir_fee = I80F48!(0.1).into();
+
+        // At full utilization the base rate is 3, fee revenue maxes out at 3 * 0.2 = 0.6
+        assert_eq!(
+            config.utilization_for_fee_revenue(I80F48!(0.7)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn zeroed_config_is_all_zero() {
+        let config = BankConfig::zeroed_config();
+        assert!(bytemuck::bytes_of(&config).iter().all(|b| *b == 0));
+
+        assert_eq!(I80F48::from(config.asset_weight_init), I80F48::ZERO);
+        assert_eq!(I80F48::from(config.liability_weight_maint), I80F48::ZERO);
+        assert_eq!(config.deposit_limit, 0);
+        assert_eq!(config.operational_state, BankOperationalState::Paused);
+        assert_eq!(config.oracle_keys, [Pubkey::default(); MAX_ORACLE_KEYS]);
+        // Distinct from the Default config, which sets non-zero weights
+        assert_ne!(config, BankConfig::default());
+    }
+
+    #[test]
+    fn final_asset_weight_init_composes_emode_and_init_limit() {
+        let emode_tag = 3;
+        let make_bank = |emode_on: bool, limit_on: bool| {
+            let mut bank = bank_with_totals(I80F48!(1_000), I80F48::ZERO);
+            bank.config.asset_weight_init = I80F48!(0.5).into();
+            bank.emode.emode_config = EmodeConfig::from_entries(&[EmodeEntry {
+                collateral_bank_emode_tag: emode_tag,
+                flags: 0,
+                pad0: [0; 1],
+                liability_weight_init_bps: 0,
+                liability_weight_maint_bps: 0,
+                asset_weight_init: I80F48!(0.8).into(),
+                asset_weight_maint: I80F48!(0.9).into(),
+            }]);
+            bank.emode.set_emode_enabled(emode_on);
+            if limit_on {
+                // $1,000 deposited at price 1 against a $250 cap: 25% discount factor
+                bank.config.total_asset_value_init_limit = 250;
+            }
+            bank
+        };
+        let price = I80F48::ONE;
+
+        // Neither adjustment
+        let bank = make_bank(false, false);
+        assert_eq!(
+            bank.final_asset_weight_init(price, emode_tag).unwrap(),
+            I80F48!(0.5)
+        );
+
+        // Emode only
+        let bank = make_bank(true, false);
+        assert_eq!(
+            bank.final_asset_weight_init(price, emode_tag).unwrap(),
+            I80F48!(0.8)
+        );
+        // Non-matching tag falls back to the base weight
+        assert_eq!(
+            bank.final_asset_weight_init(price, emode_tag + 1).unwrap(),
+            I80F48!(0.5)
+        );
+
+        // Init limit only
+        let bank = make_bank(false, true);
+        assert_eq_with_tolerance!(
+            bank.final_asset_weight_init(price, emode_tag).unwrap(),
+            I80F48!(0.125),
+            I80F48!(0.000001)
+        );
+
+        // Both: emode relaxes first, then the discount applies
+        let bank = make_bank(true, true);
+        assert_eq_with_tolerance!(
+            bank.final_asset_weight_init(price, emode_tag).unwrap(),
+            I80F48!(0.2),
+            I80F48!(0.000001)
+        );
+    }
+
+    #[test]
+    fn change_summary_lists_only_set_fields() {
+        let opt = BankConfigOpt {
+            asset_weight_init: Some(I80F48!(0.75).into()),
+            deposit_limit: Some(1_000_000),
+            interest_rate_config: Some(InterestRateConfigOpt {
+                max_interest_rate: Some(I80F48!(2.5).into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            opt.change_summary(),
+            vec![
+                ("asset_weight_init".to_string(), "0.75".to_string()),
+                ("deposit_limit".to_string(), "1000000".to_string()),
+                (
+                    "interest_rate_config.max_interest_rate".to_string(),
+                    "2.5".to_string()
+                ),
+            ]
+        );
+        assert!(BankConfigOpt::default().change_summary().is_empty());
+    }
+
+    #[test]
+    fn is_arena_bank_follows_group_flag() {
+        let mut group = program_fee_group();
+        let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        bank.config.risk_tier = RiskTier::Collateral;
+        assert!(!bank.is_arena_bank(&group));
+
+        group.set_arena_group(true).unwrap();
+        assert!(bank.is_arena_bank(&group));
+        bank.config.risk_tier = RiskTier::Isolated;
+        assert!(bank.is_arena_bank(&group));
+    }
+
+    #[test]
+    fn emissions_remaining_native_scales_and_floors() {
+        let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        bank.emissions_remaining = I80F48!(1.2345678919).into();
+
+        assert_eq!(bank.emissions_remaining_native(6).unwrap(), 1_234_567);
+        assert_eq!(bank.emissions_remaining_native(9).unwrap(), 1_234_567_891);
+
+        // Too large for a u64 once scaled
+        bank.emissions_remaining = I80F48::from_num(u64::MAX).into();
+        assert!(bank.emissions_remaining_native(6).is_err());
+        assert!(bank.emissions_remaining_native(9).is_err());
+    }
+
+    #[test]
+    fn calc_user_emissions_splits_pro_rata() {
+        let year = 31_536_000;
+        let mut bank = bank_with_totals(I80F48!(1_000_000_000), I80F48::ZERO);
+        bank.mint_decimals = 6;
+        // 0.1 emitted tokens per whole deposited token per year
+        bank.emissions_rate = 100_000;
+        bank.emissions_remaining = I80F48!(1_000_000_000).into();
+
+        // Inactive until the lending flag is set
+        assert_eq!(
+            bank.calc_user_emissions(I80F48!(250_000_000), year)
+                .unwrap(),
+            I80F48::ZERO
+        );
+        bank.flags |= EMISSION_FLAG_LENDING_ACTIVE;
+
+        // Depositors of 250 and 750 tokens for a year: 100_000 * 250 and 100_000 * 750
+        let alice = bank
+            .calc_user_emissions(I80F48!(250_000_000), year)
+            .unwrap();
+        let bob = bank
+            .calc_user_emissions(I80F48!(750_000_000), year)
+            .unwrap();
+        assert_eq_with_tolerance!(alice, I80F48!(25_000_000), I80F48!(0.0001));
+        assert_eq_with_tolerance!(bob, I80F48!(75_000_000), I80F48!(0.0001));
+
+        // Half a year earns half
+        assert_eq_with_tolerance!(
+            bank.calc_user_emissions(I80F48!(250_000_000), year / 2)
+                .unwrap(),
+            I80F48!(12_500_000),
+            I80F48!(0.0001)
+        );
+
+        // Shares are converted to deposits at the current share value
+        bank.asset_share_value = I80F48!(2).into();
+        assert_eq_with_tolerance!(
+            bank.calc_user_emissions(I80F48!(250_000_000), year)
+                .unwrap(),
+            I80F48!(50_000_000),
+            I80F48!(0.0001)
+        );
+
+        // Capped by what remains
+        bank.emissions_remaining = I80F48!(500).into();
+        assert_eq!(
+            bank.calc_user_emissions(I80F48!(750_000_000), year)
+                .unwrap(),
+            I80F48!(500)
+        );
+    }
+
+    #[test]
+    fn configure_blocks_leaving_reduce_only_with_bad_debt() {
+        let to_operational = BankConfigOpt {
+            operational_state: Some(BankOperationalState::Operational),
+            ..Default::default()
+        };
+
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(1_100));
+        bank.config.operational_state = BankOperationalState::ReduceOnly;
+        assert!(bank.configure(&to_operational).is_err());
+        assert_eq!(
+            bank.config.operational_state,
+            BankOperationalState::ReduceOnly
+        );
+
+        // Staying in ReduceOnly is always allowed
+        bank.configure(&BankConfigOpt {
+            operational_state: Some(BankOperationalState::ReduceOnly),
+            ..Default::default()
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn configure_asset_tag_change_requires_empty_bank_or_force() {
+        let to_sol = BankConfigOpt {
+            asset_tag: Some(ASSET_TAG_SOL),
+            ..Default::default()
+        };
+
+        // Empty bank
+        let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        bank.config.risk_tier = RiskTier::Collateral;
+        bank.configure(&to_sol).unwrap();
+        assert_eq!(bank.config.asset_tag, ASSET_TAG_SOL);
+
+        // Active bank
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48::ZERO);
+        bank.config.risk_tier = RiskTier::Collateral;
+        assert_eq!(
+            bank.configure(&to_sol),
+            Err(MarginfiError::InvalidConfig.into())
+        );
+        assert_eq!(bank.config.asset_tag, ASSET_TAG_DEFAULT);
+
+        // Setting the current tag again is not a change
+        bank.configure(&BankConfigOpt {
+            asset_tag: Some(ASSET_TAG_DEFAULT),
+            ..Default::default()
+        })
+        .unwrap();
+
+        bank.configure(&BankConfigOpt {
+            force_asset_tag_change: Some(true),
+            ..to_sol
+        })
+        .unwrap();
+        assert_eq!(bank.config.asset_tag, ASSET_TAG_SOL);
+    }
+
+    #[test]
+    fn configure_freeze_requires_solvent_bank() {
+        let freeze = BankConfigOpt {
+            freeze_settings: Some(true),
+            ..Default::default()
+        };
+
+        let mut solvent = bank_with_totals(I80F48!(1_000), I80F48!(1_000));
+        solvent.configure(&freeze).unwrap();
+        assert!(solvent.get_flag(FREEZE_SETTINGS));
+
+        let mut insolvent = bank_with_totals(I80F48!(1_000), I80F48!(1_001));
+        assert_eq!(
+            insolvent.configure(&freeze),
+            Err(MarginfiError::BankHasBadDebt.into())
+        );
+        assert!(!insolvent.get_flag(FREEZE_SETTINGS));
+
+        // Unfreezing is always allowed
+        insolvent.update_flag(true, FREEZE_SETTINGS);
+        insolvent
+            .configure(&BankConfigOpt {
+                freeze_settings: Some(false),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(!insolvent.get_flag(FREEZE_SETTINGS));
+    }
+
+    #[test]
+    fn configure_allows_leaving_reduce_only_when_solvent() {
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(900));
+        bank.config.operational_state = BankOperationalState::ReduceOnly;
+        bank.configure(&BankConfigOpt {
+            operational_state: Some(BankOperationalState::Operational),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            bank.config.operational_state,
+            BankOperationalState::Operational
+        );
+    }
+
+    #[test]
+    fn projected_share_values_match_accrual() {
+        let group = program_fee_group();
+        let mut bank = bank_with_totals(I80F48!(1_000_000), I80F48!(600_000));
+        bank.config.interest_rate_config.insurance_ir_fee = I80F48!(0.05).into();
+        bank.config.interest_rate_config.protocol_fixed_fee_apr = I80F48!(0.01).into();
+
+        let now = 86_400;
+        let (asset_share_value, liability_share_value) =
+            bank.projected_share_values(now, &group).unwrap();
+        // Projecting leaves the bank untouched
+        assert_eq!(bank.last_update, 0);
+        assert_eq!(I80F48::from(bank.asset_share_value), I80F48::ONE);
+
+        bank.accrue_interest(
+            now,
+            &group,
+            #[cfg(not(feature = "client"))]
+            Pubkey::default(),
+        )
+        .unwrap();
+        assert!(asset_share_value > I80F48::ONE);
+        assert_eq!(asset_share_value, I80F48::from(bank.asset_share_value));
+        assert_eq!(
+            liability_share_value,
+            I80F48::from(bank.liability_share_value)
+        );
+
+        // Nothing pending right after accrual
+        assert_eq!(
+            bank.projected_share_values(now, &group).unwrap(),
+            (asset_share_value, liability_share_value)
+        );
+    }
+
+    #[test]
+    fn collateral_utilization_zero_and_partial() {
+        let bank = bank_with_totals(I80F48!(1_000), I80F48!(100));
+        assert_eq!(
+            bank.collateral_utilization(I80F48::ZERO).unwrap(),
+            I80F48::ZERO
+        );
+        assert_eq!(
+            bank.collateral_utilization(I80F48!(250)).unwrap(),
+            I80F48!(0.25)
+        );
+
+        let empty = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        assert_eq!(
+            empty.collateral_utilization(I80F48!(250)).unwrap(),
+            I80F48::ZERO
+        );
+    }
+
+    #[test]
+    fn socialize_loss_impact_previews_socialize_loss() {
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(500));
+        bank.asset_share_value = I80F48!(1.2).into();
+
+        let preview = bank.socialize_loss_impact(I80F48!(120)).unwrap();
+        // Preview leaves the bank untouched
+        assert_eq!(I80F48::from(bank.asset_share_value), I80F48!(1.2));
+
+        bank.socialize_loss(I80F48!(120)).unwrap();
+        assert_eq!(preview, I80F48::from(bank.asset_share_value));
+        // (1,000 * 1.2 - 120) / 1,000
+        assert_eq_with_tolerance!(preview, I80F48!(1.08), I80F48!(0.000001));
+    }
+
+    #[test]
+    fn net_interest_margin_is_fees_plus_utilization_spread() {
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(400));
+        bank.config.interest_rate_config.insurance_ir_fee = I80F48!(0.1).into();
+        bank.config.interest_rate_config.insurance_fee_fixed_apr = I80F48!(0.01).into();
+        bank.config.interest_rate_config.protocol_ir_fee = I80F48!(0.05).into();
+        bank.config.interest_rate_config.protocol_fixed_fee_apr = I80F48!(0.02).into();
+
+        let rates = bank.current_interest_rates().unwrap();
+        let ur = bank.get_utilization_rate().unwrap();
+        let fees = rates.group_fee_apr + rates.insurance_fee_apr;
+        let utilization_spread = rates.base_rate_apr * (I80F48::ONE - ur);
+
+        let nim = bank.net_interest_margin().unwrap();
+        assert_eq!(
+            nim,
+            bank.effective_borrow_apr().unwrap() - bank.effective_lending_apr().unwrap()
+        );
+        assert_eq_with_tolerance!(nim, fees + utilization_spread, I80F48!(0.000001));
+
+        // ur 0.4: base 0.32, fees 0.32 * 0.15 + 0.03 = 0.078, spread 0.32 * 0.6 = 0.192
+        assert_eq_with_tolerance!(nim, I80F48!(0.27), I80F48!(0.000001));
+
+        // Capping the lending rate below 0.32 * 0.4 = 0.128 widens the margin
+        bank.config.interest_rate_config.max_lending_rate = I80F48!(0.1).into();
+        let capped_nim = bank.net_interest_margin().unwrap();
+        assert_eq!(
+            capped_nim,
+            bank.effective_borrow_apr().unwrap() - bank.effective_lending_apr().unwrap()
+        );
+        // 0.32 * 1.15 + 0.03 - 0.1
+        assert_eq_with_tolerance!(capped_nim, I80F48!(0.298), I80F48!(0.000001));
+    }
+
+    #[test]
+    fn validate_group_config_clean_and_inconsistent() {
+        let group_key = Pubkey::new_unique();
+        let group = MarginfiGroup {
+            banks: 2,
+            ..Default::default()
+        };
+        let make_bank = |emode_tag: u16| {
+            let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+            bank.group = group_key;
+            bank.config.risk_tier = RiskTier::Collateral;
+            bank.config.asset_weight_init = I80F48!(0.5).into();
+            bank.config.asset_weight_maint = I80F48!(0.6).into();
+            bank.config.liability_weight_init = I80F48!(1.2).into();
+            bank.config.liability_weight_maint = I80F48!(1.1).into();
+            bank.emode.emode_tag = emode_tag;
+            bank
+        };
+        let mut sol = make_bank(1);
+        sol.emode.emode_config = EmodeConfig::from_entries(&[EmodeEntry {
+            collateral_bank_emode_tag: 2,
+            flags: 0,
+            pad0: [0; 1],
+            liability_weight_init_bps: 0,
+            liability_weight_maint_bps: 0,
+            asset_weight_init: I80F48!(0.8).into(),
+            asset_weight_maint: I80F48!(0.9).into(),
+        }]);
+        let lst = make_bank(2);
+
+        assert!(validate_group_config(&group_key, &group, &[sol, lst]).is_ok());
+
+        // Bank from another group
+        let mut stray = lst;
+        stray.group = Pubkey::new_unique();
+        assert!(validate_group_config(&group_key, &group, &[sol, stray]).is_err());
+
+        // Invalid bank config
+        let mut bad_weights = lst;
+        bad_weights.config.asset_weight_init = I80F48!(1.5).into();
+        assert!(validate_group_config(&group_key, &group, &[sol, bad_weights]).is_err());
+
+        // Emode entry for a tag no bank uses
+        let mut untagged = lst;
+        untagged.emode.emode_tag = 3;
+        assert!(validate_group_config(&group_key, &group, &[sol, untagged]).is_err());
+
+        // Bank count mismatch
+        assert!(validate_group_config(&group_key, &group, &[sol]).is_err());
+    }
+
+    #[test]
+    fn recommended_poll_interval_halves_max_age() {
+        let config_with_age = |oracle_max_age| BankConfig {
+            oracle_max_age,
+            ..Default::default()
+        };
+
+        assert_eq!(config_with_age(1).recommended_poll_interval(), 1);
+        assert_eq!(config_with_age(3).recommended_poll_interval(), 1);
+        assert_eq!(config_with_age(30).recommended_poll_interval(), 15);
+        assert_eq!(config_with_age(3_600).recommended_poll_interval(), 1_800);
+        assert_eq!(
+            config_with_age(u16::MAX).recommended_poll_interval(),
+            u16::MAX / 2
+        );
+
+        // Unset max age on Pyth falls back to the default max age
+        let pyth = BankConfig {
+            oracle_max_age: 0,
+            oracle_setup: OracleSetup::PythPushOracle,
+            ..Default::default()
+        };
+        assert_eq!(
+            pyth.recommended_poll_interval() as u64,
+            MAX_PYTH_ORACLE_AGE / 2
+        );
+        assert_eq!(config_with_age(0).recommended_poll_interval(), 1);
+    }
+
+    #[test]
+    fn resolve_weights_applies_emode_liability_weights() {
+        let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        bank.config.asset_weight_init = I80F48!(0.5).into();
+        bank.config.asset_weight_maint = I80F48!(0.6).into();
+        bank.config.liability_weight_init = I80F48!(1.3).into();
+        bank.config.liability_weight_maint = I80F48!(1.2).into();
+        let asset_only = EmodeEntry {
+            collateral_bank_emode_tag: 1,
+            flags: 0,
+            pad0: [0; 1],
+            liability_weight_init_bps: 0,
+            liability_weight_maint_bps: 0,
+            asset_weight_init: I80F48!(0.8).into(),
+            asset_weight_maint: I80F48!(0.9).into(),
+        };
+        let with_liab = EmodeEntry {
+            collateral_bank_emode_tag: 2,
+            liability_weight_init_bps: 11_000,
+            liability_weight_maint_bps: 10_500,
+            ..asset_only
+        };
+        bank.emode.emode_config = EmodeConfig::from_entries(&[asset_only, with_liab]);
+
+        // Emode off: bank weights
+        assert_eq!(
+            bank.resolve_weights(RequirementType::Initial, 2),
+            (I80F48!(0.5), I80F48!(1.3))
+        );
+
+        bank.emode.set_emode_enabled(true);
+        assert_eq!(
+            bank.resolve_weights(RequirementType::Initial, 1),
+            (I80F48!(0.8), I80F48!(1.3))
+        );
+        // 11_000 and 10_500 bps
+        assert_eq!(
+            bank.resolve_weights(RequirementType::Initial, 2),
+            (I80F48!(0.8), with_liab.liability_weight_init())
+        );
+        assert_eq_with_tolerance!(
+            with_liab.liability_weight_init(),
+            I80F48!(1.1),
+            I80F48!(0.000001)
+        );
+        assert_eq!(
+            bank.resolve_weights(RequirementType::Maintenance, 2),
+            (I80F48!(0.9), with_liab.liability_weight_maint())
+        );
+        assert_eq_with_tolerance!(
+            with_liab.liability_weight_maint(),
+            I80F48!(1.05),
+            I80F48!(0.000001)
+        );
+        // Unknown tag and equity are unaffected
+        assert_eq!(
+            bank.resolve_weights(RequirementType::Maintenance, 3),
+            (I80F48!(0.6), I80F48!(1.2))
+        );
+        assert_eq!(
+            bank.resolve_weights(RequirementType::Equity, 2),
+            (I80F48::ONE, I80F48::ONE)
+        );
+    }
+
+    #[test]
+    fn sweep_dust_shares_zeroes_only_residue() {
+        let epsilon = I80F48!(0.0000001);
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(0.000000000000001));
+
+        bank.sweep_dust_shares(epsilon);
+        assert_eq!(I80F48::from(bank.total_liability_shares), I80F48::ZERO);
+        assert_eq!(I80F48::from(bank.total_asset_shares), I80F48!(1_000));
+
+        // A small but real balance survives
+        let mut bank = bank_with_totals(I80F48!(0.000000000000001), I80F48!(0.5));
+        bank.sweep_dust_shares(epsilon);
+        assert_eq!(I80F48::from(bank.total_asset_shares), I80F48::ZERO);
+        assert_eq!(I80F48::from(bank.total_liability_shares), I80F48!(0.5));
+    }
+
+    #[test]
+    fn interest_rate_config_ir_fees_capped_at_one() {
+        let mut config = bank_with_totals(I80F48::ZERO, I80F48::ZERO)
+            .config
+            .interest_rate_config;
+        assert!(config.validate().is_ok());
+
+        // Exactly 100% of interest is allowed
+        config.protocol_ir_fee = I80F48!(0.6).into();
+        config.insurance_ir_fee = I80F48!(0.4).into();
+        assert!(config.validate().is_ok());
+
+        config.insurance_ir_fee = I80F48!(0.41).into();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn insurance_topup_needed_to_reach_target() {
+        let bank = bank_with_totals(I80F48!(2_000), I80F48!(1_000));
+        assert_eq_with_tolerance!(
+            bank.insurance_coverage_ratio(I80F48!(30)).unwrap(),
+            I80F48!(0.03),
+            I80F48!(0.000001)
+        );
+
+        // 5% of 1,000 = 50, 30 already in the vault
+        assert_eq_with_tolerance!(
+            bank.insurance_topup_needed(I80F48!(30), I80F48!(0.05))
+                .unwrap(),
+            I80F48!(20),
+            I80F48!(0.000001)
+        );
+        // Already above target
+        assert_eq!(
+            bank.insurance_topup_needed(I80F48!(80), I80F48!(0.05))
+                .unwrap(),
+            I80F48::ZERO
+        );
+        // No liabilities, nothing to cover
+        let empty = bank_with_totals(I80F48!(2_000), I80F48::ZERO);
+        assert_eq!(
+            empty
+                .insurance_topup_needed(I80F48::ZERO, I80F48!(0.05))
+                .unwrap(),
+            I80F48::ZERO
+        );
+    }
+
+    #[test]
+    fn effective_program_fee_inherited_or_overridden() {
+        let group = program_fee_group();
+        let mut bank = bank_with_totals(I80F48!(1_000_000), I80F48!(600_000));
+
+        assert_eq!(
+            bank.effective_program_fee(&group).unwrap(),
+            (PROTOCOL_FEE_FIXED_DEFAULT, PROTOCOL_FEE_RATE_DEFAULT)
+        );
+        let inherited = bank.projected_share_values(86_400, &group).unwrap();
+
+        bank.config
+            .set_program_fee_override(Some((I80F48::ZERO, I80F48!(0.1))));
+        assert_eq!(
+            bank.effective_program_fee(&group).unwrap(),
+            (I80F48::ZERO, I80F48!(0.1))
+        );
+        // The override feeds into accrual: borrowers pay a different rate, lenders are unaffected
+        let overridden = bank.projected_share_values(86_400, &group).unwrap();
+        assert_eq!(overridden.0, inherited.0);
+        assert_ne!(overridden.1, inherited.1);
+
+        bank.config.set_program_fee_override(None);
+        assert_eq!(
+            bank.effective_program_fee(&group).unwrap(),
+            (PROTOCOL_FEE_FIXED_DEFAULT, PROTOCOL_FEE_RATE_DEFAULT)
+        );
+    }
+
+    #[test]
+    fn shares_at_value_historical() {
+        assert_eq!(
+            shares_at_value(I80F48!(1_000), I80F48::ONE).unwrap(),
+            I80F48!(1_000)
+        );
+        assert_eq!(
+            shares_at_value(I80F48!(1_000), I80F48!(1.25)).unwrap(),
+            I80F48!(800)
+        );
+        assert!(shares_at_value(I80F48!(1_000), I80F48::ZERO).is_err());
+
+        // Matches the live path at the bank's current share value
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48::ZERO);
+        bank.asset_share_value = I80F48!(1.25).into();
+        assert_eq!(
+            bank.get_asset_shares(I80F48!(1_000)).unwrap(),
+            shares_at_value(I80F48!(1_000), I80F48!(1.25)).unwrap()
+        );
+    }
+
+    #[test]
+    fn program_fees_enabled_controls_program_fee_accrual() {
+        let mut group = program_fee_group();
+        let bank = bank_with_totals(I80F48!(1_000_000), I80F48!(600_000));
+        assert!(bank.program_fees_enabled(&group.get_group_bank_config()));
+        let rates = bank
+            .interest_rate_calculator(&group)
+            .unwrap()
+            .calc_interest_rate(I80F48!(0.6))
+            .unwrap();
+        assert!(rates.protocol_fee_apr > I80F48::ZERO);
+
+        group.set_program_fee_enabled(false);
+        assert!(!bank.program_fees_enabled(&group.get_group_bank_config()));
+        let rates = bank
+            .interest_rate_calculator(&group)
+            .unwrap()
+            .calc_interest_rate(I80F48!(0.6))
+            .unwrap();
+        assert_eq!(rates.protocol_fee_apr, I80F48::ZERO);
+    }
+
+    #[test]
+    fn effective_lending_apr_capped_by_max_lending_rate() {
+        // ur 0.5, lending apr 0.4 * 0.5 = 0.2
+        let mut bank = bank_with_totals(I80F48!(100), I80F48!(50));
+        assert_eq!(bank.config.get_max_lending_rate(), None);
+        let uncapped = bank.effective_lending_apr().unwrap();
+        assert_eq_with_tolerance!(uncapped, I80F48!(0.2), I80F48!(0.000001));
+
+        // A cap above the current rate changes nothing
+        bank.config.interest_rate_config.max_lending_rate = I80F48!(0.5).into();
+        assert_eq!(bank.effective_lending_apr().unwrap(), uncapped);
+
+        bank.config.interest_rate_config.max_lending_rate = I80F48!(0.1).into();
+        assert_eq!(bank.config.get_max_lending_rate(), Some(I80F48!(0.1)));
+        assert_eq!(bank.effective_lending_apr().unwrap(), I80F48!(0.1));
+        // Borrowers pay the same either way
+        assert_eq_with_tolerance!(
+            bank.effective_borrow_apr().unwrap(),
+            I80F48!(0.4),
+            I80F48!(0.000001)
+        );
+
+        assert!(bank.config.interest_rate_config.validate().is_ok());
+        bank.config.interest_rate_config.max_lending_rate = I80F48!(-0.1).into();
+        assert!(bank.config.interest_rate_config.validate().is_err());
+    }
+
+    #[test]
+    fn audit_flags_soft_risks() {
+        let mut config = bank_with_totals(I80F48::ZERO, I80F48::ZERO).config;
+        config.risk_tier = RiskTier::Collateral;
+        config.asset_weight_init = I80F48!(0.95).into();
+        config.asset_weight_maint = I80F48!(0.97).into();
+        config.liability_weight_init = I80F48::ONE.into();
+        config.liability_weight_maint = I80F48::ONE.into();
+        assert!(config.validate().is_ok());
+
+        let warnings = config.audit();
+        assert_eq!(
+            warnings,
+            vec![
+                ConfigWarning::HighLtv { ltv: I80F48!(0.95) },
+                ConfigWarning::InitLimitInactive,
+            ]
+        );
+        assert_eq!(warnings[0].severity(), ConfigWarningSeverity::High);
+        assert_eq!(warnings[1].severity(), ConfigWarningSeverity::Low);
+
+        // Conservative weights with an init limit: nothing to report
+        config.asset_weight_init = I80F48!(0.7).into();
+        config.total_asset_value_init_limit = 1_000_000;
+        assert!(config.audit().is_empty());
+    }
+
+    #[test]
+    fn group_weighted_ltv_weights_by_deposits() {
+        assert_eq!(group_weighted_ltv(&[]).unwrap(), I80F48::ZERO);
+
+        let mut small = bank_with_totals(I80F48!(100), I80F48::ZERO);
+        small.config.asset_weight_init = I80F48!(0.5).into();
+        small.config.liability_weight_init = I80F48::ONE.into();
+        let mut large = bank_with_totals(I80F48!(300), I80F48::ZERO);
+        large.config.asset_weight_init = I80F48!(0.9).into();
+        large.config.liability_weight_init = I80F48::ONE.into();
+
+        // (0.5 * 100 + 0.9 * 300) / 400 = 0.8
+        assert_eq_with_tolerance!(
+            group_weighted_ltv(&[small, large]).unwrap(),
+            I80F48!(0.8),
+            I80F48!(0.000001)
+        );
+
+        // Banks without deposits don't move the average
+        let empty = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        assert_eq_with_tolerance!(
+            group_weighted_ltv(&[small, large, empty]).unwrap(),
+            I80F48!(0.8),
+            I80F48!(0.000001)
+        );
+    }
+
+    #[test]
+    fn fee_wallet_rotation_respects_timelock() {
+        let mut group = MarginfiGroup::default();
+        let old_wallet = Pubkey::new_unique();
+        let new_wallet = Pubkey::new_unique();
+        group.fee_state_cache.global_fee_wallet = old_wallet;
+
+        // Nothing pending
+        assert!(group.commit_fee_wallet(1_000, 0).is_err());
+
+        group.propose_fee_wallet(new_wallet, 1_000);
+        assert_eq!(group.extension().pending_fee_wallet, new_wallet);
+
+        // Premature commit
+        assert_eq!(
+            group.commit_fee_wallet(1_000 + 86_399, 86_400),
+            Err(MarginfiError::TimelockNotElapsed.into())
+        );
+        assert_eq!(group.fee_state_cache.global_fee_wallet, old_wallet);
+
+        // Delay elapsed
+        group.commit_fee_wallet(1_000 + 86_400, 86_400).unwrap();
+        assert_eq!(group.fee_state_cache.global_fee_wallet, new_wallet);
+        assert_eq!(group.extension().pending_fee_wallet, Pubkey::default());
+        assert_eq!(group.extension().pending_fee_wallet_proposed_at, 0);
+    }
+
+    #[test]
+    fn accrue_interest_caps_elapsed_time() {
+        let three_years = 3 * MAX_ACCRUAL_INTERVAL as i64;
+        let mut stale = bank_with_totals(I80F48!(100), I80F48!(50));
+        let mut one_year = stale;
+
+        let applied = stale
+            .accrue_interest_capped(
+                three_years,
+                &MarginfiGroup::default(),
+                #[cfg(not(feature = "client"))]
+                Pubkey::default(),
+            )
+            .unwrap();
+        assert_eq!(applied, MAX_ACCRUAL_INTERVAL as i64);
+        assert_eq!(stale.last_update, MAX_ACCRUAL_INTERVAL as i64);
+
+        // Same result as a bank that was only a year stale
+        one_year
+            .accrue_interest(
+                MAX_ACCRUAL_INTERVAL as i64,
+                &MarginfiGroup::default(),
+                #[cfg(not(feature = "client"))]
+                Pubkey::default(),
+            )
+            .unwrap();
+        assert_eq!(stale.asset_share_value, one_year.asset_share_value);
+        assert_eq!(stale.liability_share_value, one_year.liability_share_value);
+        assert!(I80F48::from(stale.liability_share_value) > I80F48::ONE);
+
+        // The remaining two years are still accrued by later calls
+        for year in 2..=3 {
+            let applied = stale
+                .accrue_interest_capped(
+                    three_years,
+                    &MarginfiGroup::default(),
+                    #[cfg(not(feature = "client"))]
+                    Pubkey::default(),
+                )
+                .unwrap();
+            assert_eq!(applied, MAX_ACCRUAL_INTERVAL as i64);
+            assert_eq!(stale.last_update, year * MAX_ACCRUAL_INTERVAL as i64);
+        }
+        assert!(
+            I80F48::from(stale.liability_share_value)
+                > I80F48::from(one_year.liability_share_value)
+        );
+
+        // Caught up, nothing left to apply
+        let applied = stale
+            .accrue_interest_capped(
+                three_years,
+                &MarginfiGroup::default(),
+                #[cfg(not(feature = "client"))]
+                Pubkey::default(),
+            )
+            .unwrap();
+        assert_eq!(applied, 0);
+        assert_eq!(stale.last_update, three_years);
+    }
+
+    #[test]
+    fn accrue_interest_catches_up_in_capped_steps() {
+        let three_years = 3 * MAX_ACCRUAL_INTERVAL as i64;
+        let mut bank = bank_with_totals(I80F48!(100), I80F48!(50));
+        let mut stepped = bank;
+
+        bank.accrue_interest(
+            three_years,
+            &MarginfiGroup::default(),
+            #[cfg(not(feature = "client"))]
+            Pubkey::default(),
+        )
+        .unwrap();
+        assert_eq!(bank.last_update, three_years);
+        assert!(stale_banks(&[bank], three_years).is_empty());
+
+        // Same as three explicit one year steps
+        for _ in 0..3 {
+            stepped
+                .accrue_interest_capped(
+                    three_years,
+                    &MarginfiGroup::default(),
+                    #[cfg(not(feature = "client"))]
+                    Pubkey::default(),
+                )
+                .unwrap();
+        }
+        assert_eq!(bank.asset_share_value, stepped.asset_share_value);
+        assert_eq!(bank.liability_share_value, stepped.liability_share_value);
+    }
+
+    #[test]
+    fn per_second_rate_recovers_apr() {
+        let config = InterestRateConfig {
+            insurance_ir_fee: I80F48!(0.1).into(),
+            protocol_fixed_fee_apr: I80F48!(0.01).into(),
+            ..bank_with_totals(I80F48::ZERO, I80F48::ZERO)
+                .config
+                .interest_rate_config
+        };
+
+        for ur in [I80F48::ZERO, I80F48!(0.25), I80F48!(0.5), I80F48!(0.9)] {
+            let apr = config
+                .create_interest_rate_calculator(&MarginfiGroup::default())
+                .calc_interest_rate(ur)
+                .unwrap()
+                .borrowing_rate_apr;
+            let per_second = config.per_second_rate(ur).unwrap();
+
+            assert!(per_second > I80F48::ZERO);
+            assert_eq_with_tolerance!(per_second * SECONDS_PER_YEAR, apr, I80F48!(0.000001));
+        }
+    }
+
+    #[test]
+    fn weighted_liability_maint_below_init() {
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(500));
+        bank.liability_share_value = I80F48!(1.1).into();
+        bank.config.liability_weight_init = I80F48!(1.25).into();
+        bank.config.liability_weight_maint = I80F48!(1.1).into();
+
+        let shares = I80F48!(100);
+        let price = I80F48!(2);
+
+        // 100 shares * 1.1 = 110 tokens, * 2 = 220, * 1.1 = 242
+        let maint = bank.calc_weighted_liability_maint(shares, price).unwrap();
+        assert_eq_with_tolerance!(maint, I80F48!(242), I80F48!(0.000001));
+
+        let init = calc_value(
+            bank.get_liability_amount(shares).unwrap(),
+            price,
+            bank.mint_decimals,
+            Some(bank.config.liability_weight_init.into()),
+        )
+        .unwrap();
+        assert_eq_with_tolerance!(init, I80F48!(275), I80F48!(0.000001));
+        assert!(maint < init);
+
+        assert_eq!(
+            bank.calc_weighted_liability_maint(I80F48::ZERO, price)
+                .unwrap(),
+            I80F48::ZERO
+        );
+    }
+
+    #[test]
+    fn group_extension_overlays_padding() {
+        let mut group = MarginfiGroup::default();
+        assert_eq!(*group.extension(), GroupExtension::default());
+
+        let wallet = Pubkey::new_unique();
+        group.extension_mut().pending_fee_wallet = wallet;
+        group.extension_mut().pending_fee_wallet_proposed_at = 1_234;
+
+        assert_eq!(group.extension().pending_fee_wallet, wallet);
+        assert_eq!(group.extension().pending_fee_wallet_proposed_at, 1_234);
+
+        // Stored in the first bytes of the padding, the rest is untouched
+        let bytes: &[u8] = bytemuck::cast_slice(&group._padding_0);
+        assert_eq!(&bytes[..32], wallet.as_ref());
+        assert_eq!(&bytes[32..40], &1_234i64.to_le_bytes());
+        assert!(bytes[40..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    /// ur: 0.5, borrow apr: 0.4
+    fn effective_borrow_cost_short_vs_long_hold() {
+        let mut bank = bank_with_totals(I80F48!(100), I80F48!(50));
+        bank.config.interest_rate_config.protocol_origination_fee = I80F48!(0.01).into();
+        let amount = I80F48!(1_000);
+
+        // 10 origination + 1_000 * 0.4 / 365 interest
+        let one_day = bank.effective_borrow_cost(amount, 86_400).unwrap();
+        assert_eq_with_tolerance!(one_day, I80F48!(11.09589), I80F48!(0.00001));
+        // The origination fee dominates a short hold
+        assert!(one_day < I80F48!(10) * 2);
+
+        // 10 origination + 400 interest
+        let one_year = bank.effective_borrow_cost(amount, 31_536_000).unwrap();
+        assert_eq_with_tolerance!(one_year, I80F48!(410), I80F48!(0.00001));
+
+        assert_eq_with_tolerance!(
+            bank.effective_borrow_cost(amount, 0).unwrap(),
+            I80F48!(10),
+            I80F48!(0.00001)
+        );
+    }
+
+    #[test]
+    fn stale_banks_past_oracle_max_age() {
+        let now = 10_000;
+        let bank_updated_at = |last_update: i64, oracle_max_age: u16| {
+            let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+            bank.last_update = last_update;
+            bank.config.oracle_max_age = oracle_max_age;
+            bank
+        };
+
+        let banks = [
+            // Fresh
+            bank_updated_at(now - 30, 60),
+            // Stale
+            bank_updated_at(now - 61, 60),
+            // Exactly at the max age is still fresh
+            bank_updated_at(now - 120, 120),
+            // Stale
+            bank_updated_at(0, 300),
+        ];
+
+        let stale = stale_banks(&banks, now);
+        assert_eq!(stale.len(), 2);
+        assert_eq!(stale[0].last_update, now - 61);
+        assert_eq!(stale[1].last_update, 0);
+        assert!(stale_banks(&[], now).is_empty());
+    }
+
+    #[test]
+    fn implied_accrued_rate_from_share_value_delta() {
+        let mut bank = bank_with_totals(I80F48!(100), I80F48!(50));
+        let prior = bank.financial_snapshot();
+        assert_eq!(prior.liability_share_value, I80F48::ONE);
+
+        // 1% growth over a tenth of a year is 10% annualized
+        bank.liability_share_value = I80F48!(1.01).into();
+        assert_eq_with_tolerance!(
+            bank.implied_accrued_rate(&prior, 3_153_600).unwrap(),
+            I80F48!(0.1),
+            I80F48!(0.000001)
+        );
+
+        // Matches the curve after a real accrual
+        let mut bank = bank_with_totals(I80F48!(100), I80F48!(50));
+        let prior = bank.financial_snapshot();
+        let borrow_apr = bank.effective_borrow_apr().unwrap();
+        bank.accrue_interest(
+            3_600,
+            &MarginfiGroup::default(),
+            #[cfg(not(feature = "client"))]
+            Pubkey::default(),
+        )
+        .unwrap();
+        assert_eq_with_tolerance!(
+            bank.implied_accrued_rate(&prior, 3_600).unwrap(),
+            borrow_apr,
+            I80F48!(0.000001)
+        );
+
+        assert!(bank.implied_accrued_rate(&prior, 0).is_err());
+    }
+
+    #[test]
+    fn group_protocol_equity_nets_bad_debt() {
+        let solvent = bank_with_totals(I80F48!(100), I80F48!(50));
+        // 30 of liabilities not backed by any asset
+        let insolvent = bank_with_totals(I80F48!(70), I80F48!(100));
+
+        assert_eq!(
+            group_protocol_equity(&[solvent, insolvent], &[I80F48!(20), I80F48!(15)]).unwrap(),
+            I80F48!(5)
+        );
+        // Bad debt can exceed the insurance
+        assert_eq!(
+            group_protocol_equity(&[insolvent], &[I80F48!(10)]).unwrap(),
+            I80F48!(-20)
+        );
+        assert_eq!(group_protocol_equity(&[], &[]).unwrap(), I80F48::ZERO);
+        assert!(group_protocol_equity(&[solvent], &[]).is_err());
+    }
+
+    #[test]
+    fn set_oracle_keys_checks_count_and_duplicates() {
+        let mut config = bank_with_totals(I80F48::ZERO, I80F48::ZERO).config;
+        let (feed, lst_mint, sol_pool) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+
+        config
+            .set_oracle_keys(&[feed, lst_mint, sol_pool], OracleSetup::StakedWithPythPush)
+            .unwrap();
+        assert_eq!(config.oracle_setup, OracleSetup::StakedWithPythPush);
+        assert_eq!(
+            config.oracle_keys,
+            [
+                feed,
+                lst_mint,
+                sol_pool,
+                Pubkey::default(),
+                Pubkey::default()
+            ]
+        );
+
+        // Switching setups clears the keys no longer used
+        config
+            .set_oracle_keys(&[feed], OracleSetup::SwitchboardPull)
+            .unwrap();
+        assert_eq!(config.oracle_setup, OracleSetup::SwitchboardPull);
+        assert_eq!(config.oracle_keys[0], feed);
+        assert!(config.oracle_keys[1..]
+            .iter()
+            .all(|k| *k == Pubkey::default()));
+
+        // Wrong count
+        assert_eq!(
+            config.set_oracle_keys(&[feed, lst_mint], OracleSetup::PythPushOracle),
+            Err(MarginfiError::WrongNumberOfOracleAccounts.into())
+        );
+        assert_eq!(
+            config.set_oracle_keys(&[feed], OracleSetup::StakedWithPythPush),
+            Err(MarginfiError::WrongNumberOfOracleAccounts.into())
+        );
+        // Duplicate or default keys
+        assert_eq!(
+            config.set_oracle_keys(&[feed, feed, sol_pool], OracleSetup::StakedWithPythPush),
+            Err(MarginfiError::WrongOracleAccountKeys.into())
+        );
+        assert_eq!(
+            config.set_oracle_keys(&[Pubkey::default()], OracleSetup::PythPushOracle),
+            Err(MarginfiError::WrongOracleAccountKeys.into())
+        );
+        // Failed calls leave the config alone
+        assert_eq!(config.oracle_setup, OracleSetup::SwitchboardPull);
+        assert_eq!(config.oracle_keys[0], feed);
+    }
+
+    #[test]
+    /// optimal ur: 0.5, plateau: 0.4, max: 3
+    fn marginal_rate_on_each_side_of_kink() {
+        let config = bank_with_totals(I80F48::ZERO, I80F48::ZERO)
+            .config
+            .interest_rate_config;
+
+        // 0.4 / 0.5
+        let below = config.marginal_rate(I80F48!(0.25)).unwrap();
+        assert_eq_with_tolerance!(below, I80F48!(0.8), I80F48!(0.000001));
+        assert_eq!(config.marginal_rate(I80F48!(0.5)).unwrap(), below);
+
+        // (3 - 0.4) / (1 - 0.5)
+        let above = config.marginal_rate(I80F48!(0.75)).unwrap();
+        assert_eq_with_tolerance!(above, I80F48!(5.2), I80F48!(0.000001));
+        assert!(above > below);
+    }
+
+    #[test]
+    fn depositor_earnings_breakdown_separates_interest_and_emissions() {
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(500));
+        bank.asset_share_value = I80F48!(1.05).into();
+
+        // 200 shares * (1.05 - 1)
+        let (interest, emissions) = bank
+            .depositor_earnings_breakdown(I80F48!(200), I80F48::ONE, I80F48!(3))
+            .unwrap();
+        assert_eq_with_tolerance!(interest, I80F48!(10), I80F48!(0.000001));
+        assert_eq!(emissions, I80F48!(3));
+
+        // Share value fell since the prior value
+        let (interest, _) = bank
+            .depositor_earnings_breakdown(I80F48!(200), I80F48!(1.1), I80F48::ZERO)
+            .unwrap();
+        assert!(interest < I80F48::ZERO);
+    }
+
+    #[test]
+    fn fee_routing_falls_back_to_fee_vault() {
+        let mut group = MarginfiGroup::default();
+        group.fee_state_cache.global_fee_wallet = Pubkey::new_unique();
+        let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        bank.insurance_vault = Pubkey::new_unique();
+        bank.fee_vault = Pubkey::new_unique();
+
+        assert_eq!(
+            bank.fee_routing(&group),
+            FeeRouting {
+                insurance_fees: bank.insurance_vault,
+                group_fees: bank.fee_vault,
+                program_fees: group.fee_state_cache.global_fee_wallet,
+            }
+        );
+
+        bank.fees_destination_account = Pubkey::new_unique();
+        let routing = bank.fee_routing(&group);
+        assert_eq!(routing.group_fees, bank.fees_destination_account);
+        assert_eq!(routing.insurance_fees, bank.insurance_vault);
+        assert_eq!(
+            routing.program_fees,
+            group.fee_state_cache.global_fee_wallet
+        );
+    }
+
+    #[test]
+    fn max_liquidator_repay_bound_by_liquidity() {
+        // Liability: 6 decimals at $2
+        let mut liability_bank = bank_with_totals(I80F48!(1_000_000_000), I80F48!(500_000_000));
+        liability_bank.mint_decimals = 6;
+        // Collateral: 20 tokens (9 decimals) at $5 available in the vault, i.e. $100
+        let mut collateral_bank =
+            bank_with_totals(I80F48!(100_000_000_000), I80F48!(80_000_000_000));
+        collateral_bank.mint_decimals = 9;
+
+        // Close factor allows 100 tokens ($200), liquidity only covers 50 tokens ($100)
+        assert_eq!(
+            liability_bank
+                .max_liquidator_repay(
+                    I80F48!(0.5),
+                    I80F48!(200_000_000),
+                    I80F48!(2),
+                    &collateral_bank,
+                    I80F48!(5)
+                )
+                .unwrap(),
+            I80F48!(50_000_000)
+        );
+        // Close factor allows 10 tokens ($20), within liquidity
+        assert_eq!(
+            liability_bank
+                .max_liquidator_repay(
+                    I80F48!(0.5),
+                    I80F48!(20_000_000),
+                    I80F48!(2),
+                    &collateral_bank,
+                    I80F48!(5)
+                )
+                .unwrap(),
+            I80F48!(10_000_000)
+        );
+        assert!(liability_bank
+            .max_liquidator_repay(
+                I80F48::ZERO,
+                I80F48!(200_000_000),
+                I80F48!(2),
+                &collateral_bank,
+                I80F48!(5)
+            )
+            .is_err());
+        assert!(liability_bank
+            .max_liquidator_repay(
+                I80F48!(0.5),
+                I80F48!(200_000_000),
+                I80F48::ZERO,
+                &collateral_bank,
+                I80F48!(5)
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn oracle_setup_changed_on_setup_or_keys() {
+        let mut previous = bank_with_totals(I80F48::ZERO, I80F48::ZERO).config;
+        previous
+            .set_oracle_keys(&[Pubkey::new_unique()], OracleSetup::PythPushOracle)
+            .unwrap();
+
+        let mut config = previous;
+        assert!(!config.oracle_setup_changed(&previous));
+
+        // Same setup, different feed
+        config
+            .set_oracle_keys(&[Pubkey::new_unique()], OracleSetup::PythPushOracle)
+            .unwrap();
+        assert!(config.oracle_setup_changed(&previous));
+
+        // Same feed, different setup
+        let mut config = previous;
+        config.oracle_setup = OracleSetup::SwitchboardPull;
+        assert!(config.oracle_setup_changed(&previous));
+
+        // Unrelated changes don't count
+        let mut config = previous;
+        config.oracle_max_age = 120;
+        assert!(!config.oracle_setup_changed(&previous));
+    }
+
+    #[test]
+    fn withdrawal_share_value_with_and_without_fee() {
+        let mut bank = bank_with_totals(I80F48!(100), I80F48!(50));
+        bank.asset_share_value = I80F48!(1.2).into();
+        assert_eq!(bank.withdrawal_share_value().unwrap(), I80F48!(1.2));
+
+        // 1.2 * (1 - 0.05)
+        bank.config.withdrawal_fee = I80F48!(0.05).into();
+        assert_eq_with_tolerance!(
+            bank.withdrawal_share_value().unwrap(),
+            I80F48!(1.14),
+            I80F48!(0.000001)
+        );
+        // Consistent with the fee charged on an actual withdrawal
+        let amount = bank.get_asset_amount(I80F48!(100)).unwrap();
+        assert_eq_with_tolerance!(
+            amount - bank.calc_withdrawal_fee(amount).unwrap(),
+            bank.withdrawal_share_value().unwrap() * I80F48!(100),
+            I80F48!(0.000001)
+        );
+    }
+
+    #[test]
+    fn group_bad_debt_sums_shortfalls() {
+        let healthy = bank_with_totals(I80F48!(100), I80F48!(50));
+        let fully_utilized = bank_with_totals(I80F48!(100), I80F48!(100));
+        let insolvent = bank_with_totals(I80F48!(70), I80F48!(100));
+
+        assert_eq!(healthy.bad_debt().unwrap(), I80F48::ZERO);
+        assert_eq!(insolvent.bad_debt().unwrap(), I80F48!(30));
+        assert_eq!(
+            group_bad_debt(&[healthy, insolvent, fully_utilized]).unwrap(),
+            I80F48!(30)
+        );
+        assert_eq!(
+            group_bad_debt(&[healthy, fully_utilized]).unwrap(),
+            I80F48::ZERO
+        );
+        assert_eq!(group_bad_debt(&[]).unwrap(), I80F48::ZERO);
+    }
+
+    #[test]
+    fn emode_borrow_power_delta_with_active_entry() {
+        let emode_tag = 3;
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48::ZERO);
+        bank.config.asset_weight_init = I80F48!(0.5).into();
+        bank.emode.emode_config = EmodeConfig::from_entries(&[EmodeEntry {
+            collateral_bank_emode_tag: emode_tag,
+            flags: 0,
+            pad0: [0; 1],
+            liability_weight_init_bps: 0,
+            liability_weight_maint_bps: 0,
+            asset_weight_init: I80F48!(0.8).into(),
+            asset_weight_maint: I80F48!(0.9).into(),
+        }]);
+        bank.emode.set_emode_enabled(true);
+
+        // 100 tokens at $2: $160 with emode vs $100 without
+        let delta = bank
+            .emode_borrow_power_delta(I80F48!(100), I80F48!(2), emode_tag)
+            .unwrap();
+        assert_eq_with_tolerance!(delta, I80F48!(60), I80F48!(0.000001));
+
+        // Tag doesn't match
+        assert_eq!(
+            bank.emode_borrow_power_delta(I80F48!(100), I80F48!(2), emode_tag + 1)
+                .unwrap(),
+            I80F48::ZERO
+        );
+        // Emode disabled
+        bank.emode.set_emode_enabled(false);
+        assert_eq!(
+            bank.emode_borrow_power_delta(I80F48!(100), I80F48!(2), emode_tag)
+                .unwrap(),
+            I80F48::ZERO
+        );
+    }
+
+    #[test]
+    fn insolvency_headroom_near_insolvent_bank() {
+        let near_insolvent = bank_with_totals(I80F48!(1_000), I80F48!(999));
+        assert_eq!(near_insolvent.insolvency_headroom().unwrap(), I80F48::ONE);
+        assert!(!near_insolvent.should_auto_pause().unwrap());
+
+        let insolvent = bank_with_totals(I80F48!(1_000), I80F48!(1_001));
+        assert_eq!(insolvent.insolvency_headroom().unwrap(), I80F48::ZERO);
+        assert!(insolvent.should_auto_pause().unwrap());
+
+        let healthy = bank_with_totals(I80F48!(1_000), I80F48!(600));
+        assert_eq!(healthy.insolvency_headroom().unwrap(), I80F48!(400));
+    }
+
+    #[test]
+    fn per_slot_rate_recovers_apr() {
+        let config = InterestRateConfig {
+            insurance_ir_fee: I80F48!(0.1).into(),
+            protocol_fixed_fee_apr: I80F48!(0.01).into(),
+            ..bank_with_totals(I80F48::ZERO, I80F48::ZERO)
+                .config
+                .interest_rate_config
+        };
+        // ~400ms slots
+        let slots_per_year: u64 = 78_840_000;
+
+        for ur in [I80F48::ZERO, I80F48!(0.25), I80F48!(0.5), I80F48!(0.9)] {
+            let apr = config
+                .create_interest_rate_calculator(&MarginfiGroup::default())
+                .calc_interest_rate(ur)
+                .unwrap()
+                .borrowing_rate_apr;
+            let per_slot = config.per_slot_rate(ur, slots_per_year).unwrap();
+
+            assert!(per_slot > I80F48::ZERO);
+            assert_eq_with_tolerance!(
+                per_slot * I80F48::from_num(slots_per_year),
+                apr,
+                I80F48!(0.000001)
+            );
+        }
+
+        assert!(config.per_slot_rate(I80F48!(0.5), 0).is_err());
+    }
+
+    #[test]
+    fn group_discount_factor_vs_per_bank_discount() {
+        // $1,000 of deposits in this bank against a $2,000 cap
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48::ZERO);
+        bank.config.total_asset_value_init_limit = 2_000;
+        let price = I80F48::ONE;
+
+        // Per bank: under the cap
+        assert_eq!(
+            bank.maybe_get_asset_weight_init_discount(price).unwrap(),
+            None
+        );
+        assert_eq!(bank.group_discount_factor(I80F48!(1_000)).unwrap(), None);
+
+        // The same asset holds $4,000 across the group: 50% discount
+        assert_eq!(
+            bank.group_discount_factor(I80F48!(4_000)).unwrap(),
+            Some(I80F48!(0.5))
+        );
+
+        // Per bank discount once the bank alone exceeds the cap
+        bank.total_asset_shares = I80F48!(8_000).into();
+        assert_eq!(
+            bank.maybe_get_asset_weight_init_discount(price).unwrap(),
+            bank.group_discount_factor(I80F48!(8_000)).unwrap()
+        );
+        assert_eq!(
+            bank.maybe_get_asset_weight_init_discount(price).unwrap(),
+            Some(I80F48!(0.25))
+        );
+
+        // Inactive limit
+        bank.config.total_asset_value_init_limit = TOTAL_ASSET_VALUE_INIT_LIMIT_INACTIVE;
+        assert_eq!(
+            bank.group_discount_factor(I80F48!(1_000_000)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn risk_params_packed_round_trip() {
+        let config = BankConfig {
+            asset_weight_init: I80F48!(0.75).into(),
+            asset_weight_maint: I80F48!(0.85).into(),
+            liability_weight_init: I80F48!(1.25).into(),
+            liability_weight_maint: I80F48!(1.1).into(),
+            deposit_limit: 1_000_000,
+            borrow_limit: 500_000,
+            risk_tier: RiskTier::Isolated,
+            ..Default::default()
+        };
+
+        let packed = config.risk_params_packed();
+        assert_eq!(packed.len(), 81);
+        let unpacked = BankConfig::from_packed(&packed).unwrap();
+        assert_eq!(unpacked.asset_weight_init, config.asset_weight_init);
+        assert_eq!(unpacked.asset_weight_maint, config.asset_weight_maint);
+        assert_eq!(unpacked.liability_weight_init, config.liability_weight_init);
+        assert_eq!(
+            unpacked.liability_weight_maint,
+            config.liability_weight_maint
+        );
+        assert_eq!(unpacked.deposit_limit, config.deposit_limit);
+        assert_eq!(unpacked.borrow_limit, config.borrow_limit);
+        assert_eq!(unpacked.risk_tier, config.risk_tier);
+        assert_eq!(unpacked.risk_params_packed(), packed);
+
+        let mut bad_tier = packed;
+        bad_tier[80] = 2;
+        assert!(BankConfig::from_packed(&bad_tier).is_err());
+    }
+
+    #[test]
+    fn apply_rate_step_twice_equals_compounded_step() {
+        let mut stepped = bank_with_totals(I80F48!(1_000), I80F48!(600));
+        let mut compounded = stepped;
+
+        stepped.apply_rate_step(I80F48!(0.01)).unwrap();
+        stepped.apply_rate_step(I80F48!(0.02)).unwrap();
+        // (1.01 * 1.02) - 1
+        compounded.apply_rate_step(I80F48!(0.0302)).unwrap();
+
+        assert_eq_with_tolerance!(
+            I80F48::from(stepped.liability_share_value),
+            I80F48!(1.0302),
+            I80F48!(0.000001)
+        );
+        assert_eq_with_tolerance!(
+            I80F48::from(stepped.liability_share_value),
+            I80F48::from(compounded.liability_share_value),
+            I80F48!(0.000001)
+        );
+        assert_eq_with_tolerance!(
+            I80F48::from(stepped.asset_share_value),
+            I80F48::from(compounded.asset_share_value),
+            I80F48!(0.000001)
+        );
+
+        // Lenders receive exactly what borrowers owe: 600 * 0.0302
+        let total_assets = stepped
+            .get_asset_amount(stepped.total_asset_shares.into())
+            .unwrap();
+        assert_eq_with_tolerance!(total_assets, I80F48!(1_018.12), I80F48!(0.000001));
+    }
+
+    #[test]
+    fn status_badge_each_state() {
+        let operational = |total_assets, total_liabilities| {
+            let mut bank = bank_with_totals(total_assets, total_liabilities);
+            bank.config.operational_state = BankOperationalState::Operational;
+            bank
+        };
+
+        assert_eq!(
+            operational(I80F48!(100), I80F48!(50)).status_badge(),
+            BankStatus::Healthy
+        );
+        assert_eq!(
+            operational(I80F48!(100), I80F48!(51)).status_badge(),
+            BankStatus::HighUtilization
+        );
+
+        let mut bank = operational(I80F48!(95), I80F48!(10));
+        bank.config.deposit_limit = 100;
+        assert_eq!(bank.status_badge(), BankStatus::NearCap);
+        bank.config.deposit_limit = 101;
+        assert_eq!(bank.status_badge(), BankStatus::Healthy);
+        bank.config.borrow_limit = 10;
+        assert_eq!(bank.status_badge(), BankStatus::NearCap);
+
+        let mut bank = operational(I80F48!(100), I80F48!(90));
+        bank.config.operational_state = BankOperationalState::ReduceOnly;
+        assert_eq!(bank.status_badge(), BankStatus::ReduceOnly);
+        bank.config.operational_state = BankOperationalState::Paused;
+        assert_eq!(bank.status_badge(), BankStatus::Paused);
+
+        // Insolvency outranks every other state
+        bank.total_liability_shares = I80F48!(101).into();
+        assert_eq!(bank.status_badge(), BankStatus::Insolvent);
+    }
+
+    #[test]
+    fn emode_adjusted_liquidation_price_on_and_off() {
+        let emode_tag = 3;
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48::ZERO);
+        bank.config.asset_weight_maint = I80F48!(0.8).into();
+        bank.config.liability_weight_maint = I80F48::ONE.into();
+        bank.emode.emode_config = EmodeConfig::from_entries(&[EmodeEntry {
+            collateral_bank_emode_tag: emode_tag,
+            flags: 0,
+            pad0: [0; 1],
+            liability_weight_init_bps: 0,
+            liability_weight_maint_bps: 0,
+            asset_weight_init: I80F48!(0.8).into(),
+            asset_weight_maint: I80F48!(0.9).into(),
+        }]);
+
+        // 100 tokens backing $80 of debt: liquidatable at 80 / (100 * 0.8)
+        let emode_off = bank
+            .emode_adjusted_liquidation_price(I80F48!(100), 0, I80F48!(80), emode_tag)
+            .unwrap();
+        assert_eq_with_tolerance!(emode_off, I80F48::ONE, I80F48!(0.000001));
+
+        // 80 / (100 * 0.9)
+        bank.emode.set_emode_enabled(true);
+        let emode_on = bank
+            .emode_adjusted_liquidation_price(I80F48!(100), 0, I80F48!(80), emode_tag)
+            .unwrap();
+        assert_eq_with_tolerance!(emode_on, I80F48!(0.888888), I80F48!(0.000001));
+        assert!(emode_on < emode_off);
+
+        // Tag doesn't match
+        assert_eq!(
+            bank.emode_adjusted_liquidation_price(I80F48!(100), 0, I80F48!(80), emode_tag + 1)
+                .unwrap(),
+            emode_off
+        );
+    }
+
+    #[test]
+    fn assert_solvent_after_withdraw_boundary() {
+        let bank = bank_with_totals(I80F48!(100), I80F48!(60));
+
+        assert!(bank.assert_solvent_after_withdraw(I80F48!(39.99)).is_ok());
+        // Exactly the available liquidity leaves liabilities fully covered
+        assert!(bank.assert_solvent_after_withdraw(I80F48!(40)).is_ok());
+        assert_eq!(
+            bank.assert_solvent_after_withdraw(I80F48!(40.0001)),
+            Err(MarginfiError::InsufficientLiquidity.into())
+        );
+
+        // Nothing can leave an insolvent bank
+        let insolvent = bank_with_totals(I80F48!(100), I80F48!(101));
+        assert!(insolvent
+            .assert_solvent_after_withdraw(I80F48::ZERO)
+            .is_ok());
+        assert_eq!(
+            insolvent.assert_solvent_after_withdraw(I80F48!(0.0001)),
+            Err(MarginfiError::InsufficientLiquidity.into())
+        );
+    }
+
+    #[test]
+    fn caller_capabilities_per_role() {
+        let admin = Pubkey::new_unique();
+        let emode_admin = Pubkey::new_unique();
+        let mut group = MarginfiGroup {
+            admin,
+            emode_admin,
+            ..Default::default()
+        };
+
+        assert_eq!(group.caller_capabilities(admin), CAPABILITY_ADMIN);
+        assert_eq!(
+            group.caller_capabilities(emode_admin),
+            CAPABILITY_EMODE_ADMIN
+        );
+        assert_eq!(group.caller_capabilities(Pubkey::new_unique()), 0);
+
+        group.emode_admin = admin;
+        assert_eq!(
+            group.caller_capabilities(admin),
+            CAPABILITY_ADMIN | CAPABILITY_EMODE_ADMIN
+        );
+
+        // An unset emode admin doesn't grant anything to the default key
+        group.emode_admin = Pubkey::default();
+        assert_eq!(group.caller_capabilities(Pubkey::default()), 0);
+    }
+
+    #[test]
+    fn net_borrow_proceeds_gross_vs_net() {
+        let mut bank = bank_with_totals(I80F48!(100), I80F48!(50));
+        bank.config.interest_rate_config.protocol_origination_fee = I80F48!(0.01).into();
+        let requested = I80F48!(1_000);
+
+        // 1_000 - 10 origination - 25 program fee
+        let net = bank
+            .net_borrow_proceeds(requested, &program_fee_group())
+            .unwrap();
+        assert_eq_with_tolerance!(net, I80F48!(965), I80F48!(0.000001));
+        assert!(net < requested);
+
+        // Program fees disabled: only the origination fee is deducted
+        let net = bank
+            .net_borrow_proceeds(requested, &MarginfiGroup::default())
+            .unwrap();
+        assert_eq_with_tolerance!(net, I80F48!(990), I80F48!(0.000001));
+
+        // No fees at all
+        bank.config.interest_rate_config.protocol_origination_fee = I80F48::ZERO.into();
+        assert_eq!(
+            bank.net_borrow_proceeds(requested, &MarginfiGroup::default())
+                .unwrap(),
+            requested
+        );
+    }
+
+    #[test]
+    fn assert_shares_non_negative_detects_corruption() {
+        let bank = bank_with_totals(I80F48!(100), I80F48!(60));
+        assert_eq!(bank.share_totals(), (I80F48!(100), I80F48!(60)));
+        assert!(bank.assert_shares_non_negative().is_ok());
+        assert!(bank_with_totals(I80F48::ZERO, I80F48::ZERO)
+            .assert_shares_non_negative()
+            .is_ok());
+
+        let corrupted = bank_with_totals(I80F48!(100), I80F48!(-0.5));
+        assert_eq!(corrupted.share_totals(), (I80F48!(100), I80F48!(-0.5)));
+        assert_eq!(
+            corrupted.assert_shares_non_negative(),
+            Err(MarginfiError::NegativeShareTotal.into())
+        );
+        assert_eq!(
+            bank_with_totals(I80F48!(-1), I80F48::ZERO).assert_shares_non_negative(),
+            Err(MarginfiError::NegativeShareTotal.into())
+        );
+    }
+
+    #[test]
+    fn cross_margin_collateral_value_tier_emode_arena() {
+        let emode_tag = 3;
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48::ZERO);
+        bank.config.asset_weight_init = I80F48!(0.5).into();
+        bank.config.risk_tier = RiskTier::Collateral;
+        bank.emode.emode_config = EmodeConfig::from_entries(&[EmodeEntry {
+            collateral_bank_emode_tag: emode_tag,
+            flags: 0,
+            pad0: [0; 1],
+            liability_weight_init_bps: 0,
+            liability_weight_maint_bps: 0,
+            asset_weight_init: I80F48!(0.8).into(),
+            asset_weight_maint: I80F48!(0.9).into(),
+        }]);
+        let group = MarginfiGroup::default();
+        let mut arena_group = MarginfiGroup::default();
+        arena_group.set_arena_group(true).unwrap();
+        let value = |bank: &Bank, group: &MarginfiGroup| {
+            bank.cross_margin_collateral_value(I80F48!(100), I80F48!(2), emode_tag, group)
+                .unwrap()
+        };
+
+        // Collateral tier, 100 tokens at $2: base weight, then emode weight
+        assert_eq_with_tolerance!(value(&bank, &group), I80F48!(100), I80F48!(0.000001));
+        bank.emode.set_emode_enabled(true);
+        assert_eq_with_tolerance!(value(&bank, &group), I80F48!(160), I80F48!(0.000001));
+        assert_eq_with_tolerance!(value(&bank, &arena_group), I80F48!(160), I80F48!(0.000001));
+
+        // Isolated collateral only counts in an arena group
+        bank.config.risk_tier = RiskTier::Isolated;
+        assert_eq!(value(&bank, &group), I80F48::ZERO);
+        assert_eq_with_tolerance!(value(&bank, &arena_group), I80F48!(160), I80F48!(0.000001));
+        bank.emode.set_emode_enabled(false);
+        assert_eq!(value(&bank, &group), I80F48::ZERO);
+        assert_eq_with_tolerance!(value(&bank, &arena_group), I80F48!(100), I80F48!(0.000001));
+    }
+
+    #[test]
+    fn is_pricing_broken_without_oracle() {
+        let mut bank = bank_with_totals(I80F48!(100), I80F48!(60));
+        bank.config.oracle_setup = OracleSetup::PythPushOracle;
+        assert!(!bank.is_pricing_broken());
+
+        bank.config.oracle_setup = OracleSetup::None;
+        assert!(bank.is_pricing_broken());
+        // Liabilities alone are enough
+        bank.total_asset_shares = I80F48::ZERO.into();
+        assert!(bank.is_pricing_broken());
+
+        // An empty bank has nothing to price
+        bank.total_liability_shares = I80F48::ZERO.into();
+        assert!(!bank.is_pricing_broken());
+    }
+
+    #[test]
+    fn fee_delta_across_accrual_and_harvest() {
+        let mut bank = bank_with_totals(I80F48!(100), I80F48!(60));
+        bank.collected_insurance_fees_outstanding = I80F48!(10).into();
+        bank.collected_group_fees_outstanding = I80F48!(5).into();
+        bank.collected_program_fees_outstanding = I80F48!(2).into();
+        let prior = bank.financial_snapshot();
+        assert_eq!(
+            bank.fee_delta(&prior),
+            (I80F48::ZERO, I80F48::ZERO, I80F48::ZERO)
+        );
+
+        // Accrual grows every counter
+        bank.collected_insurance_fees_outstanding = I80F48!(15).into();
+        bank.collected_group_fees_outstanding = I80F48!(8).into();
+        bank.collected_program_fees_outstanding = I80F48!(3).into();
+        assert_eq!(
+            bank.fee_delta(&prior),
+            (I80F48!(5), I80F48!(3), I80F48::ONE)
+        );
+
+        // Group fees are harvested, then 1 more accrues: below the snapshot, clamped to zero
+        bank.collected_group_fees_outstanding = I80F48::ONE.into();
+        assert_eq!(
+            bank.fee_delta(&prior),
+            (I80F48!(5), I80F48::ZERO, I80F48::ONE)
+        );
+
+        // Measured from a snapshot after the harvest instead
+        let after_harvest = bank.financial_snapshot();
+        bank.collected_group_fees_outstanding = I80F48!(4).into();
+        assert_eq!(
+            bank.fee_delta(&after_harvest),
+            (I80F48::ZERO, I80F48!(3), I80F48::ZERO)
+        );
+    }
+
+    #[test]
+    fn bank_config_opt_is_risk_increasing() {
+        let current = BankConfig {
+            asset_weight_init: I80F48!(0.5).into(),
+            asset_weight_maint: I80F48!(0.6).into(),
+            liability_weight_init: I80F48!(1.5).into(),
+            liability_weight_maint: I80F48!(1.2).into(),
+            deposit_limit: 1_000,
+            borrow_limit: 500,
+            total_asset_value_init_limit: 10_000,
+            risk_tier: RiskTier::Isolated,
+            ..Default::default()
+        };
+
+        let risk_increasing = [
+            BankConfigOpt {
+                asset_weight_init: Some(I80F48!(0.55).into()),
+                ..Default::default()
+            },
+            BankConfigOpt {
+                liability_weight_maint: Some(I80F48!(1.1).into()),
+                ..Default::default()
+            },
+            BankConfigOpt {
+                borrow_limit: Some(501),
+                ..Default::default()
+            },
+            // Deactivating the init limit removes the cap entirely
+            BankConfigOpt {
+                total_asset_value_init_limit: Some(TOTAL_ASSET_VALUE_INIT_LIMIT_INACTIVE),
+                ..Default::default()
+            },
+            BankConfigOpt {
+                risk_tier: Some(RiskTier::Collateral),
+                ..Default::default()
+            },
+        ];
+        for opt in &risk_increasing {
+            assert!(opt.is_risk_increasing(&current));
+        }
+
+        let risk_neutral = [
+            BankConfigOpt::default(),
+            // Same values, or tighter ones
+            BankConfigOpt {
+                asset_weight_init: Some(I80F48!(0.5).into()),
+                asset_weight_maint: Some(I80F48!(0.4).into()),
+                liability_weight_init: Some(I80F48!(1.6).into()),
+                liability_weight_maint: Some(I80F48!(1.2).into()),
+                deposit_limit: Some(900),
+                borrow_limit: Some(500),
+                total_asset_value_init_limit: Some(5_000),
+                risk_tier: Some(RiskTier::Isolated),
+                ..Default::default()
+            },
+            // Unrelated fields
+            BankConfigOpt {
+                oracle_max_age: Some(120),
+                deposit_fee: Some(I80F48!(0.01).into()),
+                ..Default::default()
+            },
+        ];
+        for opt in &risk_neutral {
+            assert!(!opt.is_risk_increasing(&current));
+        }
+    }
+
+    #[test]
+    fn effective_deposit_cap_picks_binding_limit() {
+        let mut bank = bank_with_totals(I80F48::ZERO, I80F48::ZERO);
+        bank.mint_decimals = 6;
+        assert_eq!(
+            bank.effective_deposit_cap(I80F48!(2)).unwrap(),
+            I80F48::from_num(u64::MAX)
+        );
+
+        // $1_000 at $2 is 500 tokens, tighter than the 1_000 token deposit limit
+        bank.config.deposit_limit = 1_000_000_000;
+        bank.config.total_asset_value_init_limit = 1_000;
+        assert_eq!(
+            bank.effective_deposit_cap(I80F48!(2)).unwrap(),
+            I80F48!(500_000_000)
+        );
+
+        // At $0.50 the USD limit allows 2_000 tokens, so the deposit limit binds
+        assert_eq!(
+            bank.effective_deposit_cap(I80F48!(0.5)).unwrap(),
+            I80F48!(1_000_000_000)
+        );
+
+        // Only the USD limit
+        bank.config.deposit_limit = u64::MAX;
+        assert_eq!(
+            bank.effective_deposit_cap(I80F48!(0.5)).unwrap(),
+            I80F48!(2_000_000_000)
+        );
+        assert!(bank.effective_deposit_cap(I80F48::ZERO).is_err());
+
+        // Out of range decimals error instead of panicking
+        bank.mint_decimals = u8::MAX;
+        assert!(bank.effective_deposit_cap(I80F48!(0.5)).is_err());
+    }
+
+    #[test]
+    /// ur: 0.5, borrow apr: 0.4, uncapped lending apr: 0.2
+    fn max_lending_rate_caps_accrual_and_routes_excess_to_insurance() {
+        let mut bank = bank_with_totals(I80F48!(1_000), I80F48!(500));
+        bank.config.interest_rate_config.max_lending_rate = I80F48!(0.1).into();
+        let group = MarginfiGroup::default();
+        let year = 31_536_000;
+
+        bank.accrue_interest(
+            year,
+            &group,
+            #[cfg(not(feature = "client"))]
+            Pubkey::default(),
+        )
+        .unwrap();
+
+        // Depositors earn the capped 10%, borrowers still pay 40%
+        assert_eq_with_tolerance!(
+            I80F48::from(bank.asset_share_value),
+            I80F48!(1.1),
+            I80F48!(0.000001)
+        );
+        assert_eq_with_tolerance!(
+            I80F48::from(bank.liability_share_value),
+            I80F48!(1.4),
+            I80F48!(0.000001)
+        );
+        // Borrowers paid 500 * 0.4 = 200, depositors got 1_000 * 0.1 = 100, insurance the rest
+        assert_eq_with_tolerance!(
+            I80F48::from(bank.collected_insurance_fees_outstanding),
+            I80F48!(100),
+            I80F48!(0.0001)
+        );
+    }
+
+    #[test]
+    fn override_emissions_flag_accepts_emission_flags() {
+        let mut bank = Bank::default();
+
+        bank.override_emissions_flag(EMISSION_FLAG_LENDING_ACTIVE);
+        assert!(bank.get_flag(EMISSION_FLAG_LENDING_ACTIVE));
+        assert!(!bank.get_flag(EMISSION_FLAG_BORROW_ACTIVE));
+
+        bank.override_emissions_flag(EMISSION_FLAG_BORROW_ACTIVE | EMISSION_FLAG_LENDING_ACTIVE);
+        assert!(bank.get_flag(EMISSION_FLAG_BORROW_ACTIVE | EMISSION_FLAG_LENDING_ACTIVE));
+
+        bank.override_emissions_flag(0);
+        assert_eq!(bank.flags, 0);
+
+        // Group flags are not emission flags
+        let result = std::panic::catch_unwind(|| {
+            Bank::default().override_emissions_flag(FREEZE_SETTINGS);
+        });
+        assert!(result.is_err());
+    }
 }
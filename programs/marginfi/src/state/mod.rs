@@ -5,3 +5,4 @@ pub mod health_cache;
 pub mod marginfi_account;
 pub mod marginfi_group;
 pub mod price;
+pub mod utilization_tracker;
@@ -1,12 +1,16 @@
 use crate::check_eq;
-use crate::constants::{NATIVE_STAKE_ID, PYTH_ID, SPL_SINGLE_POOL_ID, SWITCHBOARD_PULL_ID};
+use crate::constants::{
+    EXP_10_I80F48, MAX_EXP_10_I80F48, NATIVE_STAKE_ID, PYTH_ID, SPL_SINGLE_POOL_ID,
+    SWITCHBOARD_PULL_ID,
+};
 use crate::errors::MarginfiError;
 use crate::prelude::MarginfiResult;
 use crate::state::marginfi_group::BankConfig;
-use crate::{check, live};
+use crate::{check, live, math_error};
 use anchor_lang::prelude::*;
 use bytemuck::{Pod, Zeroable};
 use enum_dispatch::enum_dispatch;
+use fixed::types::I80F48;
 use pyth_solana_receiver_sdk::price_update::{FeedId, PriceUpdateV2};
 use std::cell::Ref;
 use switchboard_on_demand::{
@@ -38,6 +42,21 @@ impl OracleSetup {
             _ => None,
         }
     }
+
+    /// Number of `BankConfig::oracle_keys` this setup uses. The rest must be default.
+    /// * `StakedWithPythPush`: the SOL feed, the LST mint, and the stake pool's SOL pool, in that
+    ///   order.
+    /// * Every other setup: the feed only.
+    pub fn required_key_count(&self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::PythLegacy
+            | Self::SwitchboardV2
+            | Self::PythPushOracle
+            | Self::SwitchboardPull => 1,
+            Self::StakedWithPythPush => 3,
+        }
+    }
 }
 
 // TODO: PriceBias
@@ -298,6 +317,28 @@ pub enum OraclePriceType {
     RealTime,
 }
 
+/// Scale a raw oracle price by `10^price_exponent`, e.g. a Pyth price of `12345` with exponent `-2`
+/// becomes `123.45`. Errors with `MathError` if the exponent is out of range or the result
+/// overflows.
+pub fn normalize_price(raw_price: I80F48, price_exponent: i32) -> MarginfiResult<I80F48> {
+    let abs_exponent = price_exponent.unsigned_abs() as usize;
+    check!(
+        abs_exponent < MAX_EXP_10_I80F48,
+        MarginfiError::MathError,
+        "price exponent {} out of range",
+        price_exponent
+    );
+    let scaling_factor = EXP_10_I80F48[abs_exponent];
+
+    let price = if price_exponent < 0 {
+        raw_price.checked_div(scaling_factor)
+    } else {
+        raw_price.checked_mul(scaling_factor)
+    };
+
+    Ok(price.ok_or_else(math_error!())?)
+}
+
 pub fn check_ai_and_feed_id(ai: &AccountInfo, feed_id: &FeedId) -> MarginfiResult {
     let price_feed_account = load_price_update_v2_checked(ai)?;
 
@@ -366,3 +407,33 @@ pub fn load_price_update_v2_checked(ai: &AccountInfo) -> MarginfiResult<PriceUpd
 }
 
 // TODO: pyth_price_components_to_i80f48
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_with_tolerance;
+    use fixed_macro::types::I80F48;
+
+    #[test]
+    fn normalize_price_negative_exponent() {
+        assert_eq_with_tolerance!(
+            normalize_price(I80F48!(12_345), -2).unwrap(),
+            I80F48!(123.45),
+            I80F48!(0.000001)
+        );
+        assert_eq_with_tolerance!(
+            normalize_price(I80F48!(15_000_000_000), -8).unwrap(),
+            I80F48!(150),
+            I80F48!(0.000001)
+        );
+    }
+
+    #[test]
+    fn normalize_price_positive_exponent() {
+        assert_eq!(normalize_price(I80F48!(42), 0).unwrap(), I80F48!(42));
+        assert_eq!(normalize_price(I80F48!(1.5), 3).unwrap(), I80F48!(1500));
+
+        assert!(normalize_price(I80F48::ONE, MAX_EXP_10_I80F48 as i32).is_err());
+        assert!(normalize_price(I80F48::MAX, 1).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use fixed::types::I80F48;
+
+/// Number of samples kept by a `UtilizationTracker`, older samples are overwritten.
+pub const UTILIZATION_TRACKER_CAPACITY: usize = 32;
+
+/// Ring buffer of `(timestamp, utilization)` samples for one bank, kept off-chain by keepers to
+/// smooth out short utilization spikes. Not stored in any account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtilizationTracker {
+    samples: [(i64, I80F48); UTILIZATION_TRACKER_CAPACITY],
+    /// Index the next sample is written to
+    head: usize,
+    len: usize,
+}
+
+impl Default for UtilizationTracker {
+    fn default() -> Self {
+        Self {
+            samples: [(0, I80F48::ZERO); UTILIZATION_TRACKER_CAPACITY],
+            head: 0,
+            len: 0,
+        }
+    }
+}
+
+impl UtilizationTracker {
+    /// Record `utilization` as of `now`, overwriting the oldest sample if full. A sample at the
+    /// same time as the latest one replaces it, samples older than the latest are ignored.
+    pub fn record(&mut self, utilization: I80F48, now: i64) {
+        if let Some((latest_time, _)) = self.latest() {
+            if now < latest_time {
+                return;
+            }
+            if now == latest_time {
+                self.samples[self.latest_index()] = (now, utilization);
+                return;
+            }
+        }
+
+        self.samples[self.head] = (now, utilization);
+        self.head = (self.head + 1) % UTILIZATION_TRACKER_CAPACITY;
+        self.len = (self.len + 1).min(UTILIZATION_TRACKER_CAPACITY);
+    }
+
+    /// Average utilization over the last `window` seconds up to the latest sample, where each
+    /// sample holds until the next one. The sample in effect at the start of the window counts
+    /// for the part of the window it covers, time before the oldest sample is not counted.
+    ///
+    /// Zero if there are no samples. The latest sample if there is only one, or `window` is not
+    /// positive.
+    pub fn time_weighted_average(&self, window: i64) -> I80F48 {
+        let Some((end, latest_utilization)) = self.latest() else {
+            return I80F48::ZERO;
+        };
+        let start = end.saturating_sub(window.max(0));
+
+        let mut weighted_sum = I80F48::ZERO;
+        let mut covered: i64 = 0;
+        let samples: Vec<(i64, I80F48)> = self.iter().collect();
+        for pair in samples.windows(2) {
+            let (from, utilization) = pair[0];
+            let (to, _) = pair[1];
+            let duration = to.min(end) - from.max(start);
+            if duration <= 0 {
+                continue;
+            }
+
+            weighted_sum =
+                weighted_sum.saturating_add(utilization.saturating_mul(I80F48::from_num(duration)));
+            covered += duration;
+        }
+
+        if covered == 0 {
+            return latest_utilization;
+        }
+
+        weighted_sum / I80F48::from_num(covered)
+    }
+
+    /// The most recent `(timestamp, utilization)` sample
+    pub fn latest(&self) -> Option<(i64, I80F48)> {
+        if self.len == 0 {
+            return None;
+        }
+
+        Some(self.samples[self.latest_index()])
+    }
+
+    fn latest_index(&self) -> usize {
+        (self.head + UTILIZATION_TRACKER_CAPACITY - 1) % UTILIZATION_TRACKER_CAPACITY
+    }
+
+    /// Samples from oldest to newest
+    fn iter(&self) -> impl Iterator<Item = (i64, I80F48)> + '_ {
+        let oldest =
+            (self.head + UTILIZATION_TRACKER_CAPACITY - self.len) % UTILIZATION_TRACKER_CAPACITY;
+        (0..self.len).map(move |i| self.samples[(oldest + i) % UTILIZATION_TRACKER_CAPACITY])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_with_tolerance;
+    use fixed_macro::types::I80F48;
+
+    #[test]
+    fn time_weighted_average_known_sequence() {
+        let mut tracker = UtilizationTracker::default();
+        assert_eq!(tracker.time_weighted_average(100), I80F48::ZERO);
+
+        tracker.record(I80F48!(0.2), 0);
+        assert_eq!(tracker.time_weighted_average(100), I80F48!(0.2));
+
+        tracker.record(I80F48!(0.4), 100);
+        tracker.record(I80F48!(0.8), 200);
+        tracker.record(I80F48!(0.5), 300);
+
+        // (0.2 * 100 + 0.4 * 100 + 0.8 * 100) / 300
+        assert_eq_with_tolerance!(
+            tracker.time_weighted_average(300),
+            I80F48!(0.466666),
+            I80F48!(0.000001)
+        );
+        // From t = 150: (0.4 * 50 + 0.8 * 100) / 150
+        assert_eq_with_tolerance!(
+            tracker.time_weighted_average(150),
+            I80F48!(0.666666),
+            I80F48!(0.000001)
+        );
+        // A window reaching before the first sample only covers the recorded time
+        assert_eq_with_tolerance!(
+            tracker.time_weighted_average(10_000),
+            I80F48!(0.466666),
+            I80F48!(0.000001)
+        );
+
+        // Out of order samples are ignored
+        tracker.record(I80F48!(1), 250);
+        assert_eq!(tracker.latest(), Some((300, I80F48!(0.5))));
+    }
+
+    #[test]
+    fn record_overwrites_oldest_when_full() {
+        let mut tracker = UtilizationTracker::default();
+        // The first sample is overwritten
+        tracker.record(I80F48!(1), 0);
+        for i in 1..=UTILIZATION_TRACKER_CAPACITY as i64 {
+            tracker.record(I80F48!(0.5), i * 10);
+        }
+
+        assert_eq!(tracker.time_weighted_average(i64::MAX), I80F48!(0.5));
+        assert_eq!(
+            tracker.latest(),
+            Some((UTILIZATION_TRACKER_CAPACITY as i64 * 10, I80F48!(0.5)))
+        );
+    }
+}
@@ -2,6 +2,7 @@ use crate::errors::MarginfiError;
 use crate::state::marginfi_group::Bank;
 use crate::state::marginfi_group::WrappedI80F48;
 use crate::MarginfiResult;
+use crate::{check, math_error};
 use anchor_lang::prelude::*;
 use anchor_spl::{
     token::Token,
@@ -24,6 +25,14 @@ pub fn wrapped_i80f48_to_f64(n: WrappedI80F48) -> f64 {
     as_f64
 }
 
+/// Convert a token amount to `u64` for a transfer, erroring with `MathError` if the value is
+/// negative or too large rather than wrapping. Any fractional part is truncated.
+pub fn i80f48_to_u64_checked(value: I80F48) -> MarginfiResult<u64> {
+    check!(value >= I80F48::ZERO, MarginfiError::MathError);
+
+    Ok(value.checked_to_num::<u64>().ok_or_else(math_error!())?)
+}
+
 pub fn calculate_pre_fee_spl_deposit_amount(
     mint_ai: AccountInfo,
     post_fee_amount: u64,
@@ -121,3 +130,23 @@ pub fn maybe_take_bank_mint<'info>(
         _ => panic!("unsupported token program"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixed_macro::types::I80F48;
+
+    #[test]
+    fn i80f48_to_u64_checked_bounds() {
+        assert_eq!(i80f48_to_u64_checked(I80F48::ZERO).unwrap(), 0);
+        assert_eq!(i80f48_to_u64_checked(I80F48!(1_000.9)).unwrap(), 1_000);
+        assert_eq!(
+            i80f48_to_u64_checked(I80F48::from_num(u64::MAX)).unwrap(),
+            u64::MAX
+        );
+
+        assert!(i80f48_to_u64_checked(I80F48!(-1)).is_err());
+        assert!(i80f48_to_u64_checked(I80F48!(-0.000001)).is_err());
+        assert!(i80f48_to_u64_checked(I80F48::from_num(u64::MAX) + I80F48::ONE).is_err());
+    }
+}